@@ -0,0 +1,527 @@
+use image::{imageops, RgbaImage};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::detection::find_sprite_bounds;
+use crate::error::{Result, TrueIsoError};
+
+/// Smallest atlas side tried before growing
+const MIN_ATLAS_SIZE: u32 = 32;
+
+/// Axis-aligned rectangle in atlas pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    fn contains(&self, other: &Rect) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+}
+
+/// Max-rects bin packer (best short side fit, no rotation)
+///
+/// Keeps the list of maximal free rectangles; each placement splits every free
+/// rectangle it overlaps and drops the ones contained in another.
+#[derive(Debug, Clone)]
+pub struct MaxRectsPacker {
+    free: Vec<Rect>,
+}
+
+impl MaxRectsPacker {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            free: vec![Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }],
+        }
+    }
+
+    /// Place a `width`×`height` rectangle, or `None` if it does not fit anywhere
+    pub fn insert(&mut self, width: u32, height: u32) -> Option<Rect> {
+        let placed = self
+            .free
+            .iter()
+            .filter(|free| free.width >= width && free.height >= height)
+            .min_by_key(|free| {
+                let (dw, dh) = (free.width - width, free.height - height);
+                (dw.min(dh), dw.max(dh), free.y, free.x)
+            })
+            .map(|free| Rect {
+                x: free.x,
+                y: free.y,
+                width,
+                height,
+            })?;
+
+        let mut free = Vec::with_capacity(self.free.len() + 4);
+        for rect in self.free.drain(..) {
+            if !rect.intersects(&placed) {
+                free.push(rect);
+                continue;
+            }
+            if placed.x > rect.x {
+                free.push(Rect {
+                    width: placed.x - rect.x,
+                    ..rect
+                });
+            }
+            if placed.right() < rect.right() {
+                free.push(Rect {
+                    x: placed.right(),
+                    width: rect.right() - placed.right(),
+                    ..rect
+                });
+            }
+            if placed.y > rect.y {
+                free.push(Rect {
+                    height: placed.y - rect.y,
+                    ..rect
+                });
+            }
+            if placed.bottom() < rect.bottom() {
+                free.push(Rect {
+                    y: placed.bottom(),
+                    height: rect.bottom() - placed.bottom(),
+                    ..rect
+                });
+            }
+        }
+
+        // Keep only maximal rectangles (the first of two identical ones survives)
+        let is_covered = |i: usize, rect: &Rect| {
+            free.iter()
+                .enumerate()
+                .any(|(j, other)| i != j && other.contains(rect) && (other != rect || j < i))
+        };
+        self.free = free
+            .iter()
+            .enumerate()
+            .filter(|&(i, rect)| !is_covered(i, rect))
+            .map(|(_, rect)| *rect)
+            .collect();
+
+        Some(placed)
+    }
+}
+
+/// One corrected image to place in an atlas
+#[derive(Debug, Clone)]
+pub struct AtlasSprite {
+    /// Frame name (the input path relative to its root)
+    pub name: String,
+    pub image: RgbaImage,
+    /// Tile anchor in `image` pixels; the pivot defaults to the center without one
+    pub anchor: Option<(f64, f64)>,
+}
+
+/// Where one sprite ended up in the atlas
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtlasFrame {
+    pub name: String,
+    /// Trimmed sprite in atlas pixels
+    pub frame: Rect,
+    /// Trimmed area within the untrimmed image
+    pub source: Rect,
+    /// Untrimmed image size
+    pub source_size: (u32, u32),
+    /// Pivot normalized to the untrimmed image
+    pub pivot: (f64, f64),
+}
+
+impl AtlasFrame {
+    /// True if transparent borders were cut off
+    pub fn trimmed(&self) -> bool {
+        (self.source.width, self.source.height) != self.source_size
+    }
+}
+
+/// A packed atlas image and its frames
+#[derive(Debug, Clone)]
+pub struct Atlas {
+    pub image: RgbaImage,
+    /// Frames in name order
+    pub frames: Vec<AtlasFrame>,
+}
+
+/// Trim transparent borders off each sprite and pack them into one atlas
+///
+/// Tries square power-of-two sizes (then doubling the width) until every sprite fits,
+/// and crops the result to the area actually used. Sprites are `padding` pixels apart.
+/// Fails with [`TrueIsoError::AtlasTooSmall`] if they do not fit `max_size`×`max_size`,
+/// and with [`TrueIsoError::DuplicateFrame`] if two sprites share a name (the metadata
+/// is keyed by name, so one of them would be lost).
+pub fn pack_atlas(sprites: Vec<AtlasSprite>, max_size: u32, padding: u32) -> Result<Atlas> {
+    let mut names = BTreeSet::new();
+    if let Some(sprite) = sprites.iter().find(|sprite| !names.insert(&sprite.name)) {
+        return Err(TrueIsoError::DuplicateFrame {
+            name: sprite.name.clone(),
+        });
+    }
+
+    let mut trimmed: Vec<(AtlasSprite, Rect)> = sprites
+        .into_iter()
+        .map(|sprite| {
+            let (width, height) = sprite.image.dimensions();
+            let (x, y, w, h) =
+                find_sprite_bounds(&sprite.image, 1).unwrap_or((0, 0, width, height));
+            (
+                sprite,
+                Rect {
+                    x,
+                    y,
+                    width: w,
+                    height: h,
+                },
+            )
+        })
+        .collect();
+    // Largest first packs tightest; names keep the layout deterministic
+    trimmed.sort_by(|(a, ra), (b, rb)| {
+        let key = |r: &Rect| (r.width.max(r.height), r.width * r.height);
+        key(rb).cmp(&key(ra)).then_with(|| a.name.cmp(&b.name))
+    });
+
+    let area: u64 = trimmed
+        .iter()
+        .map(|(_, r)| (r.width + padding) as u64 * (r.height + padding) as u64)
+        .sum();
+    let longest = trimmed
+        .iter()
+        .map(|(_, r)| r.width.max(r.height))
+        .max()
+        .unwrap_or(1);
+    let mut size = (((area as f64).sqrt().ceil() as u32).max(longest))
+        .next_power_of_two()
+        .max(MIN_ATLAS_SIZE);
+    let mut width = size;
+
+    let placements = loop {
+        let (w, h) = (width.min(max_size), size.min(max_size));
+        // The padding after the last row and column is cropped away again
+        let mut packer = MaxRectsPacker::new(w + padding, h + padding);
+        let placed: Option<Vec<Rect>> = trimmed
+            .iter()
+            .map(|(_, r)| packer.insert(r.width + padding, r.height + padding))
+            .collect();
+        if let Some(placed) = placed {
+            break placed;
+        }
+        if w >= max_size && h >= max_size {
+            return Err(TrueIsoError::AtlasTooSmall {
+                frames: trimmed.len(),
+                max_size,
+            });
+        }
+        if width > size {
+            size = width;
+        } else {
+            width *= 2;
+        }
+    };
+
+    let atlas_width = placements
+        .iter()
+        .map(|r| r.right() - padding)
+        .max()
+        .unwrap_or(1);
+    let atlas_height = placements
+        .iter()
+        .map(|r| r.bottom() - padding)
+        .max()
+        .unwrap_or(1);
+    let mut image = RgbaImage::new(atlas_width.max(1), atlas_height.max(1));
+
+    let mut frames: Vec<AtlasFrame> = trimmed
+        .into_iter()
+        .zip(placements)
+        .map(|((sprite, source), placed)| {
+            let view = imageops::crop_imm(
+                &sprite.image,
+                source.x,
+                source.y,
+                source.width,
+                source.height,
+            );
+            imageops::replace(&mut image, &*view, placed.x as i64, placed.y as i64);
+
+            let (width, height) = sprite.image.dimensions();
+            let pivot = match sprite.anchor {
+                Some((x, y)) => (x / width as f64, y / height as f64),
+                None => (0.5, 0.5),
+            };
+            AtlasFrame {
+                name: sprite.name,
+                frame: Rect {
+                    x: placed.x,
+                    y: placed.y,
+                    width: source.width,
+                    height: source.height,
+                },
+                source,
+                source_size: (width, height),
+                pivot,
+            }
+        })
+        .collect();
+    frames.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Atlas { image, frames })
+}
+
+/// `{x, y, w, h}` rectangle of the TexturePacker format
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FrameRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl From<Rect> for FrameRect {
+    fn from(rect: Rect) -> Self {
+        Self {
+            x: rect.x,
+            y: rect.y,
+            w: rect.width,
+            h: rect.height,
+        }
+    }
+}
+
+/// `{w, h}` size of the TexturePacker format
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FrameSize {
+    pub w: u32,
+    pub h: u32,
+}
+
+impl From<(u32, u32)> for FrameSize {
+    fn from((w, h): (u32, u32)) -> Self {
+        Self { w, h }
+    }
+}
+
+/// Normalized pivot point
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Pivot {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// One entry of the `frames` hash
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameEntry {
+    pub frame: FrameRect,
+    pub rotated: bool,
+    pub trimmed: bool,
+    pub sprite_source_size: FrameRect,
+    pub source_size: FrameSize,
+    pub pivot: Pivot,
+}
+
+/// The `meta` block
+#[derive(Debug, Clone, Serialize)]
+pub struct AtlasMeta {
+    pub app: &'static str,
+    pub version: &'static str,
+    pub image: String,
+    pub format: &'static str,
+    pub size: FrameSize,
+    pub scale: &'static str,
+}
+
+/// Atlas metadata in TexturePacker's JSON-hash format
+#[derive(Debug, Clone, Serialize)]
+pub struct AtlasMetadata {
+    pub frames: BTreeMap<String, FrameEntry>,
+    pub meta: AtlasMeta,
+}
+
+impl Atlas {
+    /// TexturePacker JSON-hash metadata, referring to the atlas image as `image_name`
+    pub fn metadata(&self, image_name: &str) -> AtlasMetadata {
+        let frames = self
+            .frames
+            .iter()
+            .map(|frame| {
+                let entry = FrameEntry {
+                    frame: frame.frame.into(),
+                    rotated: false,
+                    trimmed: frame.trimmed(),
+                    sprite_source_size: frame.source.into(),
+                    source_size: frame.source_size.into(),
+                    pivot: Pivot {
+                        x: frame.pivot.0,
+                        y: frame.pivot.1,
+                    },
+                };
+                (frame.name.clone(), entry)
+            })
+            .collect();
+
+        AtlasMetadata {
+            frames,
+            meta: AtlasMeta {
+                app: env!("CARGO_PKG_NAME"),
+                version: env!("CARGO_PKG_VERSION"),
+                image: image_name.to_string(),
+                format: "RGBA8888",
+                size: self.image.dimensions().into(),
+                scale: "1",
+            },
+        }
+    }
+
+    /// Write the metadata as JSON to `path`
+    pub fn write_metadata(&self, path: &Path, image_name: &str) -> Result<()> {
+        let io_error = |source| TrueIsoError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        let mut json = serde_json::to_vec_pretty(&self.metadata(image_name))
+            .map_err(|err| io_error(err.into()))?;
+        json.push(b'\n');
+        std::fs::write(path, json).map_err(io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn block(width: u32, height: u32, border: u32) -> RgbaImage {
+        RgbaImage::from_fn(width + 2 * border, height + 2 * border, |x, y| {
+            let inside =
+                (border..border + width).contains(&x) && (border..border + height).contains(&y);
+            if inside {
+                Rgba([200, 100, 50, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn test_packer_places_without_overlap() {
+        let mut packer = MaxRectsPacker::new(64, 64);
+        let sizes = [(32, 32), (32, 16), (16, 16), (32, 32), (16, 48), (8, 8)];
+        let placed: Vec<Rect> = sizes
+            .iter()
+            .map(|&(w, h)| packer.insert(w, h).expect("fits"))
+            .collect();
+
+        for (i, a) in placed.iter().enumerate() {
+            assert!(a.right() <= 64 && a.bottom() <= 64);
+            for b in &placed[i + 1..] {
+                assert!(!a.intersects(b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+        assert!(packer.insert(64, 64).is_none());
+    }
+
+    #[test]
+    fn test_pack_atlas_trims_and_pivots() {
+        let sprites = vec![
+            AtlasSprite {
+                name: "b.png".into(),
+                image: block(20, 10, 3),
+                anchor: Some((13.0, 16.0)),
+            },
+            AtlasSprite {
+                name: "a.png".into(),
+                image: block(30, 30, 0),
+                anchor: None,
+            },
+        ];
+        let atlas = pack_atlas(sprites, 256, 2).unwrap();
+
+        let names: Vec<&str> = atlas.frames.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["a.png", "b.png"]);
+        let (a, b) = (&atlas.frames[0], &atlas.frames[1]);
+        assert!(!a.trimmed());
+        assert_eq!(a.pivot, (0.5, 0.5));
+        assert!(b.trimmed());
+        assert_eq!(
+            b.source,
+            Rect {
+                x: 3,
+                y: 3,
+                width: 20,
+                height: 10
+            }
+        );
+        assert_eq!(b.source_size, (26, 16));
+        assert_eq!(b.pivot, (0.5, 1.0));
+        assert!(!a.frame.intersects(&b.frame));
+        assert_eq!(atlas.image.get_pixel(b.frame.x, b.frame.y)[3], 255);
+
+        let json = serde_json::to_value(atlas.metadata("atlas.png")).unwrap();
+        assert_eq!(json["frames"]["b.png"]["spriteSourceSize"]["x"], 3);
+        assert_eq!(json["frames"]["b.png"]["sourceSize"]["w"], 26);
+        assert_eq!(json["meta"]["image"], "atlas.png");
+    }
+
+    #[test]
+    fn test_pack_atlas_too_small() {
+        let sprites = (0..4)
+            .map(|i| AtlasSprite {
+                name: format!("{}.png", i),
+                image: block(40, 40, 0),
+                anchor: None,
+            })
+            .collect();
+        assert!(matches!(
+            pack_atlas(sprites, 64, 0),
+            Err(TrueIsoError::AtlasTooSmall {
+                frames: 4,
+                max_size: 64
+            })
+        ));
+    }
+
+    #[test]
+    fn test_pack_atlas_rejects_duplicate_names() {
+        let sprites = ["a.png", "b.png", "a.png"]
+            .into_iter()
+            .map(|name| AtlasSprite {
+                name: name.into(),
+                image: block(10, 10, 0),
+                anchor: None,
+            })
+            .collect();
+        assert!(matches!(
+            pack_atlas(sprites, 64, 0),
+            Err(TrueIsoError::DuplicateFrame { name }) if name == "a.png"
+        ));
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{Result, TrueIsoError};
+
+/// File extensions picked up when scanning directories and glob matches
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "bmp", "gif", "tga", "tif", "tiff", "webp",
+];
+
+/// Suffix appended to the file stem of corrected outputs
+pub const CORRECTED_SUFFIX: &str = "_corrected";
+
+/// A single image resolved from the command-line inputs
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct InputFile {
+    /// Path of the image on disk
+    pub path: PathBuf,
+    /// Path relative to the directory or glob root it was found under
+    /// (used to mirror the layout into an output directory)
+    pub relative: PathBuf,
+}
+
+/// Result of processing a single file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Angles were corrected
+    Corrected,
+    /// Angles were already within tolerance (only cropped & resized)
+    Skipped,
+}
+
+/// Tally of a batch run
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BatchSummary {
+    pub corrected: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl BatchSummary {
+    /// Record the result of one file
+    pub fn record<E>(&mut self, result: &std::result::Result<Outcome, E>) {
+        match result {
+            Ok(Outcome::Corrected) => self.corrected += 1,
+            Ok(Outcome::Skipped) => self.skipped += 1,
+            Err(_) => self.failed += 1,
+        }
+    }
+
+    /// Total number of files processed
+    pub fn total(&self) -> usize {
+        self.corrected + self.skipped + self.failed
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.failed > 0
+    }
+}
+
+/// Result of analyzing a single file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    WithinTolerance,
+    OutOfTolerance,
+    /// Detection was not confident enough, and `correct` would leave the angles alone
+    LowConfidence,
+}
+
+/// Tally of an `analyze` run
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AnalysisSummary {
+    pub within_tolerance: usize,
+    pub out_of_tolerance: usize,
+    pub low_confidence: usize,
+    pub failed: usize,
+}
+
+impl AnalysisSummary {
+    /// Record the verdict on one file
+    pub fn record<E>(&mut self, result: &std::result::Result<Verdict, E>) {
+        match result {
+            Ok(Verdict::WithinTolerance) => self.within_tolerance += 1,
+            Ok(Verdict::OutOfTolerance) => self.out_of_tolerance += 1,
+            Ok(Verdict::LowConfidence) => self.low_confidence += 1,
+            Err(_) => self.failed += 1,
+        }
+    }
+
+    /// Total number of files analyzed
+    pub fn total(&self) -> usize {
+        self.within_tolerance + self.out_of_tolerance + self.low_confidence + self.failed
+    }
+
+    /// True if every file was analyzed confidently and within tolerance
+    pub fn is_clean(&self) -> bool {
+        self.out_of_tolerance == 0 && self.low_confidence == 0 && self.failed == 0
+    }
+}
+
+/// Build the `<stem>_corrected.png` file name for an input path
+pub fn corrected_file_name(input: &Path) -> String {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    format!("{}{}.png", stem, CORRECTED_SUFFIX)
+}
+
+/// Insert `_<index>` before the extension: `tile_corrected.png` becomes `tile_corrected_2.png`
+pub fn numbered_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, index),
+    };
+    path.with_file_name(name)
+}
+
+/// Anchor sidecar of an output image: `tile_corrected.png` gets `tile_corrected.anchor.json`
+pub fn anchor_file_path(output: &Path) -> PathBuf {
+    output.with_extension("anchor.json")
+}
+
+/// First key shared by two inputs, with both of them
+///
+/// Used to catch inputs from different roots that would be written to the same file.
+pub fn find_collision<'a, K: Ord>(
+    keyed: impl IntoIterator<Item = (K, &'a InputFile)>,
+) -> Option<(K, &'a InputFile, &'a InputFile)> {
+    let mut seen = BTreeMap::new();
+    for (key, input) in keyed {
+        if let Some(first) = seen.get(&key) {
+            return Some((key, *first, input));
+        }
+        seen.insert(key, input);
+    }
+    None
+}
+
+/// Resolve files, directories and glob patterns into a sorted, de-duplicated list of images
+///
+/// Files in `exclude` (outputs written by this run, such as an atlas) are left out even when
+/// they match an input.
+pub fn collect_inputs(
+    inputs: &[PathBuf],
+    recursive: bool,
+    exclude: &[PathBuf],
+) -> Result<Vec<InputFile>> {
+    let mut files = Vec::new();
+
+    for input in inputs {
+        if input.is_dir() {
+            collect_directory(input, input, recursive, &mut files)?;
+        } else if input.is_file() {
+            let relative = PathBuf::from(input.file_name().unwrap_or_default());
+            files.push(InputFile {
+                path: input.clone(),
+                relative,
+            });
+        } else if is_glob_pattern(input) {
+            collect_glob(input, &mut files)?;
+        } else {
+            return Err(TrueIsoError::InputNotFound(input.clone()));
+        }
+    }
+
+    let exclude: Vec<PathBuf> = exclude.iter().map(|path| canonical(path)).collect();
+    files.retain(|file| !exclude.contains(&canonical(&file.path)));
+    files.sort();
+    files.dedup_by(|a, b| a.path == b.path);
+    Ok(files)
+}
+
+/// Absolute form of a path for comparisons, or the path itself if it does not exist yet
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Walk a directory, collecting image files (recursing into subdirectories if requested)
+fn collect_directory(
+    root: &Path,
+    dir: &Path,
+    recursive: bool,
+    files: &mut Vec<InputFile>,
+) -> Result<()> {
+    let io_error = |source| TrueIsoError::Io {
+        path: dir.to_path_buf(),
+        source,
+    };
+
+    for entry in std::fs::read_dir(dir).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if path.is_dir() {
+            if recursive {
+                collect_directory(root, &path, recursive, files)?;
+            }
+        } else if is_candidate_image(&path) {
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            files.push(InputFile { path, relative });
+        }
+    }
+    Ok(())
+}
+
+/// Expand a glob pattern, keeping paths relative to the pattern's literal prefix
+fn collect_glob(pattern: &Path, files: &mut Vec<InputFile>) -> Result<()> {
+    let pattern_str = pattern.to_string_lossy();
+    let root = glob_root(pattern);
+
+    let matches = glob::glob(&pattern_str).map_err(|source| TrueIsoError::InvalidPattern {
+        pattern: pattern_str.to_string(),
+        source,
+    })?;
+
+    for entry in matches {
+        let path = entry.map_err(|err| TrueIsoError::Io {
+            path: err.path().to_path_buf(),
+            source: err.into(),
+        })?;
+        if path.is_file() && is_candidate_image(&path) {
+            let relative = path.strip_prefix(&root).unwrap_or(&path).to_path_buf();
+            files.push(InputFile { path, relative });
+        }
+    }
+
+    Ok(())
+}
+
+/// Literal directory prefix of a glob pattern (everything before the first wildcard component)
+fn glob_root(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|c| !matches!(c, Component::Normal(s) if has_glob_chars(&s.to_string_lossy())))
+        .collect()
+}
+
+fn is_glob_pattern(path: &Path) -> bool {
+    has_glob_chars(&path.to_string_lossy())
+}
+
+fn has_glob_chars(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Image files with a known extension that are not outputs of a previous run
+fn is_candidate_image(path: &Path) -> bool {
+    let is_image = path
+        .extension()
+        .map(|ext| {
+            let ext = ext.to_string_lossy().to_lowercase();
+            IMAGE_EXTENSIONS.contains(&ext.as_str())
+        })
+        .unwrap_or(false);
+
+    // Also skips the numbered outputs of split sheets (`<stem>_corrected_<n>`)
+    let is_output = path
+        .file_stem()
+        .map(|stem| {
+            let stem = stem.to_string_lossy();
+            let unnumbered = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+            stem.ends_with(CORRECTED_SUFFIX)
+                || (unnumbered.len() < stem.len()
+                    && unnumbered.ends_with(&format!("{}_", CORRECTED_SUFFIX)))
+        })
+        .unwrap_or(false);
+
+    is_image && !is_output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_root() {
+        assert_eq!(
+            glob_root(Path::new("tiles/props/*.png")),
+            PathBuf::from("tiles/props")
+        );
+        assert_eq!(
+            glob_root(Path::new("tiles/**/*.png")),
+            PathBuf::from("tiles")
+        );
+        assert_eq!(glob_root(Path::new("*.png")), PathBuf::new());
+    }
+
+    #[test]
+    fn test_numbered_path() {
+        let path = numbered_path(Path::new("out/sheet_corrected.png"), 3);
+        assert_eq!(path, PathBuf::from("out/sheet_corrected_3.png"));
+        let path = anchor_file_path(&path);
+        assert_eq!(path, PathBuf::from("out/sheet_corrected_3.anchor.json"));
+    }
+
+    #[test]
+    fn test_find_collision() {
+        let input = |path: &str, relative: &str| InputFile {
+            path: PathBuf::from(path),
+            relative: PathBuf::from(relative),
+        };
+        let inputs = [
+            input("a/tile.png", "tile.png"),
+            input("a/wall.png", "wall.png"),
+            input("b/tile.png", "tile.png"),
+        ];
+        let relative = inputs.iter().map(|i| (&i.relative, i));
+        let (key, first, second) = find_collision(relative).unwrap();
+        assert_eq!(key, Path::new("tile.png"));
+        assert_eq!((first, second), (&inputs[0], &inputs[2]));
+        assert!(find_collision(inputs.iter().map(|i| (&i.path, i))).is_none());
+    }
+
+    #[test]
+    fn test_candidate_image() {
+        assert!(is_candidate_image(Path::new("tile.png")));
+        assert!(is_candidate_image(Path::new("tile.PNG")));
+        assert!(!is_candidate_image(Path::new("tile_corrected.png")));
+        assert!(!is_candidate_image(Path::new("sheet_corrected_12.png")));
+        assert!(is_candidate_image(Path::new("tile_2.png")));
+        assert!(!is_candidate_image(Path::new("notes.txt")));
+    }
+
+    #[test]
+    fn test_collect_inputs_excludes_outputs() {
+        let dir = std::env::temp_dir().join(format!("true-iso-collect-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["tile.png", "atlas.png", "atlas.json"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let inputs = [dir.clone()];
+        let all = collect_inputs(&inputs, false, &[]).unwrap();
+        // A relative spelling of the excluded path still matches
+        let exclude = [dir.join(".").join("atlas.png"), dir.join("atlas.json")];
+        let kept = collect_inputs(&inputs, false, &exclude).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(all.len(), 2);
+        let names: Vec<_> = kept.iter().map(|input| &input.relative).collect();
+        assert_eq!(names, [Path::new("tile.png")]);
+    }
+}
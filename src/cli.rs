@@ -1,59 +1,763 @@
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use std::path::{Path, PathBuf};
 
-use crate::geometry::IsometricRatio;
+use crate::batch::{corrected_file_name, numbered_path, InputFile};
+use crate::config::{parse_dimensions, parse_point, OptionOverrides, ProjectConfig};
+use crate::detection::Detector;
+use crate::geometry::{HeightPolicy, IsometricRatio};
+use crate::pipeline::{AnchorPoint, CorrectionMode, CorrectionOptions, LowConfidencePolicy};
+use crate::segment::GridLayout;
+use crate::transform::Interpolation;
 
 #[derive(Parser, Debug)]
 #[command(name = "true-iso")]
-#[command(version, about = "Correct isometric tile sprites to mathematically consistent proportions")]
+#[command(
+    version,
+    about = "Correct isometric tile sprites to mathematically consistent proportions"
+)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
-    /// Input PNG image path
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Arguments for the implicit `correct` command
+    #[command(flatten)]
+    pub correct: CorrectArgs,
+
+    /// Show detection details
+    #[arg(long, global = true)]
+    pub verbose: bool,
+
+    /// Only report warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Number of worker threads [default: number of CPU cores]
+    #[arg(short, long, global = true)]
+    pub jobs: Option<usize>,
+
+    /// Project config file [default: ./true-iso.toml if present]
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Correct images (the default when no command is given)
+    Correct(Box<CorrectArgs>),
+    /// Report the angular deviation of each image without writing anything
+    ///
+    /// Exits with a non-zero code if any image is out of tolerance or fails to load.
+    Analyze(AnalyzeArgs),
+    /// Correct images and pack them into one atlas with TexturePacker JSON-hash metadata
+    Atlas(Box<AtlasArgs>),
+}
+
+/// Input selection shared by all commands
+#[derive(Args, Debug, Clone)]
+pub struct InputArgs {
+    /// Input PNG images, directories or glob patterns (e.g. "tiles/*.png")
     #[arg(required = true)]
-    pub input: PathBuf,
+    pub inputs: Vec<PathBuf>,
+
+    /// Recurse into subdirectories of directory inputs
+    #[arg(short = 'R', long)]
+    pub recursive: bool,
+}
 
-    /// Output path [default: input_corrected.png]
+/// Target ratio, tolerance and detector parameters shared by all commands
+///
+/// Unset options fall back to the project config file, then to the library defaults.
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Detection")]
+pub struct DetectionArgs {
+    /// Target isometric ratio (e.g., "2:1") [default: 2:1]
     #[arg(short, long)]
-    pub output: Option<PathBuf>,
+    pub ratio: Option<IsometricRatio>,
+
+    /// Maximum deviation from the target angle in degrees before correcting [default: 2.0]
+    #[arg(short, long)]
+    pub tolerance: Option<f64>,
 
-    /// Target isometric ratio (e.g., "2:1")
-    #[arg(short, long, default_value = "2:1", value_parser = parse_ratio)]
-    pub ratio: IsometricRatio,
+    /// Line detector; `contour` fits the silhouette outline and ignores interior texture [default: hough]
+    #[arg(long, value_enum)]
+    pub detector: Option<Detector>,
 
-    /// Show detection details
+    /// Minimum alpha for a pixel to count as part of the sprite [default: 10]
     #[arg(long)]
-    pub verbose: bool,
+    pub alpha_threshold: Option<u8>,
+
+    /// Canny low hysteresis threshold [default: 30]
+    #[arg(long)]
+    pub canny_low: Option<f32>,
+
+    /// Canny high hysteresis threshold [default: 100]
+    #[arg(long)]
+    pub canny_high: Option<f32>,
+
+    /// Minimum number of Hough votes for a line [default: 40]
+    #[arg(long)]
+    pub vote_threshold: Option<u32>,
+
+    /// Non-maximum suppression radius in Hough space [default: 8]
+    #[arg(long)]
+    pub suppression_radius: Option<u32>,
+
+    /// Sides detected with a lower confidence are handled by --on-low-confidence [default: 0.1]
+    #[arg(long)]
+    pub min_confidence: Option<f64>,
 
-    /// Output size (longest side in pixels)
-    #[arg(short, long, default_value = "256")]
-    pub size: u32,
+    /// What to do when a side's confidence is below --min-confidence [default: skip]
+    #[arg(long, value_enum)]
+    pub on_low_confidence: Option<LowConfidencePolicy>,
 }
 
-impl Cli {
-    pub fn output_path(&self) -> PathBuf {
-        self.output.clone().unwrap_or_else(|| {
-            let stem = self.input.file_stem().unwrap_or_default().to_string_lossy();
-            let parent = self.input.parent().unwrap_or(std::path::Path::new("."));
-            parent.join(format!("{}_corrected.png", stem))
+/// Manual angles that bypass line detection
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Manual angles")]
+pub struct AngleArgs {
+    /// Use this left-sloping angle in degrees instead of detecting it (sign is ignored)
+    #[arg(long, allow_negative_numbers = true)]
+    pub left_angle: Option<f64>,
+
+    /// Use this right-sloping angle in degrees instead of detecting it
+    #[arg(long, allow_negative_numbers = true)]
+    pub right_angle: Option<f64>,
+
+    /// Ratio the sprite was drawn at (e.g. "3:1"); sets both angles unless given explicitly
+    #[arg(long)]
+    pub source_ratio: Option<IsometricRatio>,
+}
+
+/// Layout of grid-based sprite sheets
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Sprite sheets")]
+pub struct GridArgs {
+    /// Slice the input into this many columns and rows, correct each cell and repack them
+    #[arg(
+        long,
+        value_name = "COLSxROWS",
+        value_parser = parse_dimensions,
+        conflicts_with = "split"
+    )]
+    pub grid: Option<(u32, u32)>,
+
+    /// Slice the input into cells of this size (with --grid: only the first COLSxROWS cells)
+    #[arg(long, value_name = "WxH", value_parser = parse_dimensions, conflicts_with = "split")]
+    pub cell: Option<(u32, u32)>,
+
+    /// Border around the grid in pixels [default: 0]
+    #[arg(long, value_name = "PX")]
+    pub margin: Option<u32>,
+
+    /// Gap between neighbouring cells in pixels [default: 0]
+    #[arg(long, value_name = "PX")]
+    pub spacing: Option<u32>,
+}
+
+impl GridArgs {
+    /// The sheet layout, if the input is to be sliced into cells
+    pub fn layout(&self) -> Option<GridLayout> {
+        (self.grid.is_some() || self.cell.is_some()).then(|| GridLayout {
+            grid: self.grid,
+            cell: self.cell,
+            margin: self.margin.unwrap_or(0),
+            spacing: self.spacing.unwrap_or(0),
         })
     }
 }
 
-fn parse_ratio(s: &str) -> Result<IsometricRatio, String> {
-    let parts: Vec<&str> = s.split(':').collect();
-    if parts.len() != 2 {
-        return Err(format!("Invalid ratio format '{}', expected N:M", s));
+/// Per-image correction options shared by `correct` and `atlas`
+///
+/// Unset options fall back to the project config file, then to the library defaults.
+#[derive(Args, Debug, Clone)]
+pub struct CorrectionArgs {
+    #[command(flatten)]
+    pub detection: DetectionArgs,
+
+    #[command(flatten)]
+    pub angles: AngleArgs,
+
+    /// Correction transform; `perspective` fits a homography to the four top-face corners [default: affine]
+    #[arg(long, value_enum)]
+    pub mode: Option<CorrectionMode>,
+
+    /// Resampling filter for the warp and the resize; `nearest` keeps pixel art crisp
+    /// [default: bicubic]
+    #[arg(long, value_enum)]
+    pub filter: Option<Interpolation>,
+
+    /// Keep the input palette: warp with Scale2x, resize by whole pixels, snap the output
+    /// back to the source colors and redraw the diamond edges as clean 2:1 stair steps
+    #[arg(long, conflicts_with = "filter")]
+    pub pixel_art: bool,
+
+    /// Wall height of blocks: keep it, or make it one footprint edge tall [default: preserve]
+    #[arg(long, value_name = "preserve|cube")]
+    pub height: Option<HeightPolicy>,
+
+    /// Make walls this many footprint edges tall (1 is a cube)
+    #[arg(long, value_name = "RATIO", conflicts_with = "height")]
+    pub height_ratio: Option<f64>,
+
+    /// Point of the top diamond reported as the tile anchor [default: bottom-vertex]
+    #[arg(long, value_enum)]
+    pub anchor: Option<AnchorPoint>,
+
+    /// Output size (longest side in pixels) [default: 256]
+    #[arg(short, long)]
+    pub size: Option<u32>,
+
+    /// Scale the top diamond's footprint to exactly one grid tile of this width instead of
+    /// resizing to --size
+    #[arg(long, value_name = "PX", conflicts_with = "size", value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_width: Option<u32>,
+
+    /// With --tile-width, round the footprint to the nearest whole number of tiles (for
+    /// props spanning several tiles) instead of scaling it to one
+    #[arg(long, conflicts_with = "size")]
+    pub multi_tile: bool,
+
+    /// Place each sprite on a fixed WxH canvas, its footprint scaled to the canvas width
+    /// (or to one tile of --tile-width) and its anchor at --canvas-anchor
+    #[arg(long, value_name = "WxH", value_parser = parse_dimensions, conflicts_with = "size")]
+    pub canvas: Option<(u32, u32)>,
+
+    /// Canvas pixel the tile anchor is placed at [default: footprint on the bottom edge]
+    #[arg(long, value_name = "X,Y", value_parser = parse_point)]
+    pub canvas_anchor: Option<(u32, u32)>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct CorrectArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Output path for a single input [default: input_corrected.png]
+    #[arg(short, long, conflicts_with = "output_dir")]
+    pub output: Option<PathBuf>,
+
+    /// Write outputs into this directory, mirroring the input layout
+    #[arg(short = 'd', long)]
+    pub output_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub correction: CorrectionArgs,
+
+    /// Correct every separate sprite of an input on its own, writing <stem>_corrected_<n>.png
+    #[arg(long, help_heading = "Sprite sheets")]
+    pub split: bool,
+
+    #[command(flatten)]
+    pub grid: GridArgs,
+
+    /// Write the tile anchor of each output to <output stem>.anchor.json
+    #[arg(long, conflicts_with_all = ["grid", "cell"])]
+    pub anchor_file: bool,
+
+    /// Write a JSON report with one record per file ("-" for stdout)
+    #[arg(long, value_name = "PATH|-")]
+    pub report: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct AtlasArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Atlas image; the metadata is written next to it with a .json extension
+    #[arg(short, long, default_value = "atlas.png")]
+    pub output: PathBuf,
+
+    /// Largest atlas width and height in pixels [default: 2048]
+    #[arg(long, value_name = "PX")]
+    pub max_size: Option<u32>,
+
+    /// Transparent pixels between neighbouring sprites [default: 2]
+    #[arg(long, value_name = "PX")]
+    pub padding: Option<u32>,
+
+    #[command(flatten)]
+    pub correction: CorrectionArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct AnalyzeArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub detection: DetectionArgs,
+}
+
+impl Cli {
+    /// Diagnostics level selected by `--verbose` / `--quiet`
+    pub fn log_level(&self) -> tracing::Level {
+        if self.quiet {
+            tracing::Level::WARN
+        } else if self.verbose {
+            tracing::Level::DEBUG
+        } else {
+            tracing::Level::INFO
+        }
     }
 
-    let horizontal: f64 = parts[0]
-        .parse()
-        .map_err(|_| format!("Invalid horizontal value: {}", parts[0]))?;
-    let vertical: f64 = parts[1]
-        .parse()
-        .map_err(|_| format!("Invalid vertical value: {}", parts[1]))?;
+    /// Fill in options not given on the command line from the config file's top-level keys
+    pub fn apply_config(&mut self, config: &ProjectConfig) {
+        if !self.verbose && !self.quiet {
+            self.quiet = config.quiet.unwrap_or(false);
+            self.verbose = !self.quiet && config.verbose.unwrap_or(false);
+        }
+        self.jobs = self.jobs.or(config.jobs);
 
-    if horizontal <= 0.0 || vertical <= 0.0 {
-        return Err("Ratio values must be positive".to_string());
+        let recursive = config.recursive.unwrap_or(false);
+        let correct = match &mut self.command {
+            Some(Command::Correct(args)) => args.as_mut(),
+            Some(Command::Analyze(args)) => {
+                args.input.recursive |= recursive;
+                return;
+            }
+            Some(Command::Atlas(args)) => {
+                args.input.recursive |= recursive;
+                args.max_size = args.max_size.or(config.max_size);
+                args.padding = args.padding.or(config.padding);
+                return;
+            }
+            None => &mut self.correct,
+        };
+        correct.input.recursive |= recursive;
+        // A layout or --split on the command line replaces the config file's layout
+        let sheet = &mut correct.grid;
+        if sheet.grid.is_none() && sheet.cell.is_none() && !correct.split {
+            sheet.grid = config.grid;
+            sheet.cell = config.cell;
+        }
+        sheet.margin = sheet.margin.or(config.margin);
+        sheet.spacing = sheet.spacing.or(config.spacing);
+        // Grid sheets are repacked, so a configured --anchor-file only applies without a layout
+        if sheet.grid.is_none() && sheet.cell.is_none() {
+            correct.anchor_file |= config.anchor_file.unwrap_or(false);
+        }
+        correct.split |= config.split.unwrap_or(false);
+        if correct.output_dir.is_none() {
+            correct.output_dir = config.output_dir.clone();
+        }
+        if correct.report.is_none() {
+            correct.report = config.report.clone();
+        }
     }
 
-    Ok(IsometricRatio::new(horizontal, vertical))
+    /// The command to run, falling back to `correct` with the top-level arguments
+    pub fn resolved_command(&self) -> Command {
+        self.command
+            .clone()
+            .unwrap_or_else(|| Command::Correct(Box::new(self.correct.clone())))
+    }
+}
+
+impl DetectionArgs {
+    /// Options given on the command line, to be applied over the config file
+    pub fn overrides(&self) -> OptionOverrides {
+        OptionOverrides {
+            ratio: self.ratio,
+            tolerance: self.tolerance,
+            detector: self.detector,
+            alpha_threshold: self.alpha_threshold,
+            canny_low: self.canny_low,
+            canny_high: self.canny_high,
+            vote_threshold: self.vote_threshold,
+            suppression_radius: self.suppression_radius,
+            min_confidence: self.min_confidence,
+            on_low_confidence: self.on_low_confidence,
+            ..OptionOverrides::default()
+        }
+    }
+}
+
+impl CorrectionArgs {
+    /// Correction options for one input: config file layers first, command-line flags last
+    pub fn correction_options(&self, input: &Path, config: &ProjectConfig) -> CorrectionOptions {
+        let mut options = config.options_for(input);
+        OptionOverrides {
+            size: self.size,
+            tile_width: self.tile_width,
+            multi_tile: self.multi_tile.then_some(true),
+            canvas: self.canvas,
+            canvas_anchor: self.canvas_anchor,
+            mode: self.mode,
+            filter: self.filter,
+            pixel_art: self.pixel_art.then_some(true),
+            height: self.height,
+            height_ratio: self.height_ratio,
+            anchor: self.anchor,
+            source_ratio: self.angles.source_ratio,
+            left_angle: self.angles.left_angle,
+            right_angle: self.angles.right_angle,
+            ..self.detection.overrides()
+        }
+        .apply(&mut options);
+        // An explicit --size beats a tile width or canvas from the config file
+        if self.size.is_some() {
+            options.tile_width = None;
+            options.canvas = None;
+        }
+        options
+    }
+}
+
+impl CorrectArgs {
+    /// Output path for the `index`-th sprite (counting from 1) of a split input
+    pub fn sprite_output_path(&self, input: &InputFile, index: usize) -> PathBuf {
+        numbered_path(&self.output_path(input), index)
+    }
+
+    /// Output path for an input file
+    ///
+    /// Uses `--output` if given, otherwise `<stem>_corrected.png` either next to the
+    /// input or at the input's relative location inside `--output-dir`.
+    pub fn output_path(&self, input: &InputFile) -> PathBuf {
+        if let Some(output) = &self.output {
+            return output.clone();
+        }
+
+        let file_name = corrected_file_name(&input.path);
+        match &self.output_dir {
+            Some(dir) => {
+                let parent = input.relative.parent().unwrap_or(std::path::Path::new(""));
+                dir.join(parent).join(file_name)
+            }
+            None => {
+                let parent = input.path.parent().unwrap_or(std::path::Path::new("."));
+                parent.join(file_name)
+            }
+        }
+    }
+}
+
+impl AtlasArgs {
+    /// Path of the metadata file written next to the atlas image
+    pub fn metadata_path(&self) -> PathBuf {
+        self.output.with_extension("json")
+    }
+
+    /// Largest atlas width and height in pixels
+    pub fn max_size(&self) -> u32 {
+        self.max_size.unwrap_or(2048)
+    }
+
+    /// Transparent pixels between neighbouring sprites
+    pub fn padding(&self) -> u32 {
+        self.padding.unwrap_or(2)
+    }
+}
+
+impl AnalyzeArgs {
+    /// Pipeline options for one input: config file layers first, command-line flags last
+    pub fn correction_options(&self, input: &Path, config: &ProjectConfig) -> CorrectionOptions {
+        let mut options = config.options_for(input);
+        self.detection.overrides().apply(&mut options);
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_implicit_correct_command() {
+        let cli = Cli::try_parse_from(["true-iso", "tile.png", "-s", "64"]).unwrap();
+        match cli.resolved_command() {
+            Command::Correct(args) => {
+                assert_eq!(args.input.inputs, vec![PathBuf::from("tile.png")]);
+                assert_eq!(args.correction.size, Some(64));
+            }
+            other => panic!("expected correct, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_detection_options() {
+        let cli = Cli::try_parse_from([
+            "true-iso",
+            "tile.png",
+            "--alpha-threshold",
+            "128",
+            "--canny-low",
+            "10",
+            "--vote-threshold",
+            "25",
+            "--detector",
+            "contour",
+            "--filter",
+            "mitchell",
+        ])
+        .unwrap();
+        let options = cli
+            .correct
+            .correction
+            .correction_options(Path::new("tile.png"), &ProjectConfig::default());
+        assert_eq!(options.detection.alpha_threshold, 128);
+        assert_eq!(options.detection.canny_low, 10.0);
+        assert_eq!(options.detection.canny_high, 100.0);
+        assert_eq!(options.detection.vote_threshold, 25);
+        assert_eq!(options.detection.detector, Detector::Contour);
+        assert_eq!(options.interpolation, Interpolation::Mitchell);
+        assert_eq!(options.tolerance, 2.0);
+    }
+
+    #[test]
+    fn test_flags_override_config() {
+        let config: ProjectConfig = toml::from_str(
+            r#"
+            size = 64
+            tolerance = 1.0
+            jobs = 2
+            output-dir = "out"
+            report = "report.json"
+
+            [[override]]
+            match = "props/*.png"
+            size = 128
+            "#,
+        )
+        .unwrap();
+        let mut cli = Cli::try_parse_from(["true-iso", "props/tree.png", "-t", "3"]).unwrap();
+        cli.apply_config(&config);
+        assert_eq!(cli.jobs, Some(2));
+        assert_eq!(cli.correct.output_dir, Some(PathBuf::from("out")));
+        assert_eq!(cli.correct.report, Some(PathBuf::from("report.json")));
+
+        let options = cli
+            .correct
+            .correction
+            .correction_options(Path::new("props/tree.png"), &config);
+        assert_eq!(options.size, 128);
+        assert_eq!(options.tolerance, 3.0);
+    }
+
+    #[test]
+    fn test_manual_angle_options() {
+        let cli = Cli::try_parse_from([
+            "true-iso",
+            "tile.png",
+            "--source-ratio",
+            "3:1",
+            "--left-angle",
+            "-24",
+        ])
+        .unwrap();
+        let options = cli
+            .correct
+            .correction
+            .correction_options(Path::new("tile.png"), &ProjectConfig::default());
+        assert_eq!(options.angles.left, Some(-24.0));
+        assert!((options.angles.right.unwrap() - 18.435).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_height_options() {
+        let config: ProjectConfig = toml::from_str(r#"height = "cube""#).unwrap();
+        let options = |args: &[&str]| {
+            let cli = Cli::try_parse_from([&["true-iso", "block.png"], args].concat()).unwrap();
+            cli.correct
+                .correction
+                .correction_options(Path::new("block.png"), &config)
+        };
+        assert_eq!(options(&[]).height, HeightPolicy::Cube);
+        assert_eq!(
+            options(&["--height", "preserve"]).height,
+            HeightPolicy::Preserve
+        );
+        assert_eq!(
+            options(&["--height-ratio", "1.5"]).height,
+            HeightPolicy::Ratio(1.5)
+        );
+        assert!(Cli::try_parse_from(["true-iso", "a.png", "--height", "tall"]).is_err());
+    }
+
+    #[test]
+    fn test_multi_tile_option() {
+        let config: ProjectConfig = toml::from_str("tile-width = 64\nmulti-tile = true").unwrap();
+        let options = |args: &[&str], config: &ProjectConfig| {
+            let cli = Cli::try_parse_from([&["true-iso", "prop.png"], args].concat()).unwrap();
+            cli.correct
+                .correction
+                .correction_options(Path::new("prop.png"), config)
+        };
+        let configured = options(&[], &config);
+        assert_eq!(configured.tile_width, Some(64));
+        assert!(configured.multi_tile);
+        assert!(!options(&["--tile-width", "64"], &ProjectConfig::default()).multi_tile);
+        let flagged = options(
+            &["--tile-width", "64", "--multi-tile"],
+            &ProjectConfig::default(),
+        );
+        assert!(flagged.multi_tile);
+    }
+
+    #[test]
+    fn test_canvas_options() {
+        let config: ProjectConfig = toml::from_str(r#"canvas = "64x96""#).unwrap();
+        let options = |args: &[&str]| {
+            let cli = Cli::try_parse_from([&["true-iso", "tile.png"], args].concat()).unwrap();
+            cli.correct
+                .correction
+                .correction_options(Path::new("tile.png"), &config)
+        };
+        assert_eq!(options(&[]).canvas, Some((64, 96)));
+        let flagged = options(&["--canvas", "128x160", "--canvas-anchor", "64,128"]);
+        assert_eq!(flagged.canvas, Some((128, 160)));
+        assert_eq!(flagged.canvas_anchor, Some((64, 128)));
+        assert_eq!(options(&["-s", "64"]).canvas, None);
+        let conflicting = ["true-iso", "a.png", "--canvas", "64x96", "-s", "64"];
+        assert!(Cli::try_parse_from(conflicting).is_err());
+        assert!(Cli::try_parse_from(["true-iso", "a.png", "--canvas-anchor", "32"]).is_err());
+    }
+
+    #[test]
+    fn test_pixel_art_option() {
+        let config: ProjectConfig = toml::from_str("pixel-art = true").unwrap();
+        let options = |args: &[&str], config: &ProjectConfig| {
+            let cli = Cli::try_parse_from([&["true-iso", "tile.png"], args].concat()).unwrap();
+            cli.correct
+                .correction
+                .correction_options(Path::new("tile.png"), config)
+        };
+        assert!(options(&["--pixel-art"], &ProjectConfig::default()).pixel_art);
+        assert!(!options(&[], &ProjectConfig::default()).pixel_art);
+        // Leaving the flag off keeps the config file's setting
+        assert!(options(&[], &config).pixel_art);
+        let conflicting = ["true-iso", "a.png", "--pixel-art", "--filter", "bilinear"];
+        assert!(Cli::try_parse_from(conflicting).is_err());
+    }
+
+    #[test]
+    fn test_grid_options() {
+        let cli = Cli::try_parse_from([
+            "true-iso",
+            "sheet.png",
+            "--grid",
+            "8x4",
+            "--margin",
+            "2",
+            "--spacing",
+            "1",
+        ])
+        .unwrap();
+        let layout = cli.correct.grid.layout().unwrap();
+        assert_eq!(layout.grid, Some((8, 4)));
+        assert_eq!((layout.margin, layout.spacing), (2, 1));
+
+        let cli = Cli::try_parse_from(["true-iso", "sheet.png", "--cell", "64X32"]).unwrap();
+        assert_eq!(cli.correct.grid.layout().unwrap().cell, Some((64, 32)));
+        assert!(Cli::try_parse_from(["true-iso", "a.png", "--cell", "64x0"]).is_err());
+        assert!(Cli::try_parse_from(["true-iso", "a.png", "--grid", "4x4", "--split"]).is_err());
+        let cli = Cli::try_parse_from(["true-iso", "a.png"]).unwrap();
+        assert!(cli.correct.grid.layout().is_none());
+    }
+
+    #[test]
+    fn test_grid_config() {
+        let config: ProjectConfig =
+            toml::from_str("grid = \"8x4\"\nmargin = 2\nspacing = 1").unwrap();
+        let layout = |args: &[&str]| {
+            let mut cli = Cli::try_parse_from([&["true-iso", "sheet.png"], args].concat()).unwrap();
+            cli.apply_config(&config);
+            cli.correct.grid.layout()
+        };
+        let configured = layout(&[]).unwrap();
+        assert_eq!(configured.grid, Some((8, 4)));
+        assert_eq!((configured.margin, configured.spacing), (2, 1));
+
+        let flagged = layout(&["--cell", "64x64", "--spacing", "0"]).unwrap();
+        assert_eq!((flagged.grid, flagged.cell), (None, Some((64, 64))));
+        assert_eq!((flagged.margin, flagged.spacing), (2, 0));
+        assert!(layout(&["--split"]).is_none());
+        assert!(toml::from_str::<ProjectConfig>("cell = \"64x0\"").is_err());
+    }
+
+    #[test]
+    fn test_anchor_file_config() {
+        let config: ProjectConfig = toml::from_str("anchor-file = true").unwrap();
+        let anchor_file = |args: &[&str]| {
+            let mut cli = Cli::try_parse_from([&["true-iso", "tile.png"], args].concat()).unwrap();
+            cli.apply_config(&config);
+            cli.correct.anchor_file
+        };
+        assert!(anchor_file(&[]));
+        assert!(!anchor_file(&["--grid", "4x4"]));
+        assert!(
+            !Cli::try_parse_from(["true-iso", "tile.png"])
+                .unwrap()
+                .correct
+                .anchor_file
+        );
+    }
+
+    #[test]
+    fn test_atlas_command() {
+        let cli = Cli::try_parse_from([
+            "true-iso",
+            "atlas",
+            "tiles/",
+            "-o",
+            "out/tiles.png",
+            "--padding",
+            "0",
+            "-s",
+            "64",
+        ])
+        .unwrap();
+        match cli.resolved_command() {
+            Command::Atlas(args) => {
+                assert_eq!(args.metadata_path(), PathBuf::from("out/tiles.json"));
+                assert_eq!((args.max_size(), args.padding()), (2048, 0));
+                assert_eq!(args.correction.size, Some(64));
+            }
+            other => panic!("expected atlas, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_atlas_config() {
+        let config: ProjectConfig = toml::from_str("max-size = 1024\npadding = 0").unwrap();
+        let sizes = |args: &[&str]| {
+            let mut cli =
+                Cli::try_parse_from([&["true-iso", "atlas", "tiles/"], args].concat()).unwrap();
+            cli.apply_config(&config);
+            match cli.resolved_command() {
+                Command::Atlas(args) => (args.max_size(), args.padding()),
+                other => panic!("expected atlas, got {:?}", other),
+            }
+        };
+        assert_eq!(sizes(&[]), (1024, 0));
+        assert_eq!(sizes(&["--max-size", "512", "--padding", "1"]), (512, 1));
+    }
+
+    #[test]
+    fn test_analyze_command() {
+        let cli = Cli::try_parse_from([
+            "true-iso",
+            "analyze",
+            "tiles/",
+            "-t",
+            "1.5",
+            "-q",
+            "--on-low-confidence",
+            "fail",
+        ])
+        .unwrap();
+        assert!(cli.quiet);
+        match cli.resolved_command() {
+            Command::Analyze(args) => {
+                assert_eq!(args.input.inputs, vec![PathBuf::from("tiles/")]);
+                assert_eq!(args.detection.tolerance, Some(1.5));
+                let options =
+                    args.correction_options(Path::new("tiles/a.png"), &ProjectConfig::default());
+                assert_eq!(options.on_low_confidence, LowConfidencePolicy::Fail);
+            }
+            other => panic!("expected analyze, got {:?}", other),
+        }
+    }
 }
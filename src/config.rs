@@ -0,0 +1,410 @@
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::detection::Detector;
+use crate::error::{Result, TrueIsoError};
+use crate::geometry::{AngleOverride, HeightPolicy, IsometricRatio};
+use crate::pipeline::{AnchorPoint, CorrectionMode, CorrectionOptions, LowConfidencePolicy};
+use crate::transform::Interpolation;
+
+/// File name looked up in the working directory when no `--config` is given
+pub const CONFIG_FILE_NAME: &str = "true-iso.toml";
+
+/// Per-file correction settings; unset fields leave the underlying value alone
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OptionOverrides {
+    #[serde(default, deserialize_with = "deserialize_ratio")]
+    pub ratio: Option<IsometricRatio>,
+    pub tolerance: Option<f64>,
+    pub size: Option<u32>,
+    pub tile_width: Option<u32>,
+    pub multi_tile: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_dimensions")]
+    pub canvas: Option<(u32, u32)>,
+    #[serde(default, deserialize_with = "deserialize_point")]
+    pub canvas_anchor: Option<(u32, u32)>,
+    pub mode: Option<CorrectionMode>,
+    pub filter: Option<Interpolation>,
+    pub pixel_art: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_height")]
+    pub height: Option<HeightPolicy>,
+    pub height_ratio: Option<f64>,
+    pub anchor: Option<AnchorPoint>,
+    pub detector: Option<Detector>,
+    pub alpha_threshold: Option<u8>,
+    pub canny_low: Option<f32>,
+    pub canny_high: Option<f32>,
+    pub vote_threshold: Option<u32>,
+    pub suppression_radius: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_ratio")]
+    pub source_ratio: Option<IsometricRatio>,
+    pub left_angle: Option<f64>,
+    pub right_angle: Option<f64>,
+    pub min_confidence: Option<f64>,
+    pub on_low_confidence: Option<LowConfidencePolicy>,
+    /// Keys that are not options (`deny_unknown_fields` does not work with `flatten`)
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, toml::Value>,
+}
+
+impl OptionOverrides {
+    /// Overwrite every option that is set here
+    pub fn apply(&self, options: &mut CorrectionOptions) {
+        if let Some(ratio) = self.ratio {
+            options.ratio = ratio;
+        }
+        if let Some(tolerance) = self.tolerance {
+            options.tolerance = tolerance;
+        }
+        if let Some(size) = self.size {
+            options.size = size;
+        }
+        if let Some(tile_width) = self.tile_width {
+            options.tile_width = Some(tile_width);
+        }
+        if let Some(multi_tile) = self.multi_tile {
+            options.multi_tile = multi_tile;
+        }
+        if let Some(canvas) = self.canvas {
+            options.canvas = Some(canvas);
+        }
+        if let Some(point) = self.canvas_anchor {
+            options.canvas_anchor = Some(point);
+        }
+        if let Some(mode) = self.mode {
+            options.mode = mode;
+        }
+        if let Some(filter) = self.filter {
+            options.interpolation = filter;
+        }
+        if let Some(pixel_art) = self.pixel_art {
+            options.pixel_art = pixel_art;
+        }
+        // An explicit ratio wins over the named policy of the same layer
+        if let Some(height) = self.height {
+            options.height = height;
+        }
+        if let Some(factor) = self.height_ratio {
+            options.height = HeightPolicy::Ratio(factor);
+        }
+        if let Some(anchor) = self.anchor {
+            options.anchor = anchor;
+        }
+        let detection = &mut options.detection;
+        if let Some(detector) = self.detector {
+            detection.detector = detector;
+        }
+        if let Some(alpha_threshold) = self.alpha_threshold {
+            detection.alpha_threshold = alpha_threshold;
+        }
+        if let Some(canny_low) = self.canny_low {
+            detection.canny_low = canny_low;
+        }
+        if let Some(canny_high) = self.canny_high {
+            detection.canny_high = canny_high;
+        }
+        if let Some(vote_threshold) = self.vote_threshold {
+            detection.vote_threshold = vote_threshold;
+        }
+        if let Some(suppression_radius) = self.suppression_radius {
+            detection.suppression_radius = suppression_radius;
+        }
+
+        if let Some(min_confidence) = self.min_confidence {
+            options.min_confidence = min_confidence;
+        }
+        if let Some(policy) = self.on_low_confidence {
+            options.on_low_confidence = policy;
+        }
+
+        // Explicit angles win over the source ratio of the same layer
+        if let Some(source_ratio) = self.source_ratio {
+            options.angles = AngleOverride::from_ratio(&source_ratio);
+        }
+        let manual = AngleOverride::new(self.left_angle, self.right_angle);
+        options.angles.left = manual.left.or(options.angles.left);
+        options.angles.right = manual.right.or(options.angles.right);
+    }
+}
+
+/// An `[[override]]` section applied to inputs matching a glob
+#[derive(Debug, Clone, Deserialize)]
+pub struct Override {
+    /// Glob matched against input paths relative to the config file's directory
+    #[serde(rename = "match", deserialize_with = "deserialize_pattern")]
+    pub pattern: Pattern,
+    #[serde(flatten)]
+    pub options: OptionOverrides,
+}
+
+/// Contents of a `true-iso.toml` project configuration
+///
+/// Top-level keys provide defaults for the command-line options, `[[override]]`
+/// sections refine the per-file options for inputs matching their `match` glob.
+/// Overrides are applied in file order, so later sections win.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProjectConfig {
+    #[serde(flatten)]
+    pub defaults: OptionOverrides,
+    pub recursive: Option<bool>,
+    pub split: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_dimensions")]
+    pub grid: Option<(u32, u32)>,
+    #[serde(default, deserialize_with = "deserialize_dimensions")]
+    pub cell: Option<(u32, u32)>,
+    pub margin: Option<u32>,
+    pub spacing: Option<u32>,
+    pub anchor_file: Option<bool>,
+    pub output_dir: Option<PathBuf>,
+    pub report: Option<PathBuf>,
+    pub max_size: Option<u32>,
+    pub padding: Option<u32>,
+    pub jobs: Option<usize>,
+    pub verbose: Option<bool>,
+    pub quiet: Option<bool>,
+    #[serde(default, rename = "override")]
+    pub overrides: Vec<Override>,
+    /// Directory override globs are resolved against
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+impl ProjectConfig {
+    /// Parse a config file; globs are resolved relative to its directory
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|source| TrueIsoError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let mut config: Self = toml::from_str(&text).map_err(|source| TrueIsoError::Config {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let keys = config.unknown_keys();
+        if !keys.is_empty() {
+            return Err(TrueIsoError::UnknownConfigKeys {
+                path: path.to_path_buf(),
+                keys,
+            });
+        }
+        config.root = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        Ok(config)
+    }
+
+    /// Misspelled or unsupported keys, with `override[<n>].` before those of override sections
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.defaults.unknown.keys().cloned().collect();
+        for (i, section) in self.overrides.iter().enumerate() {
+            let prefix = format!("override[{}].", i + 1);
+            keys.extend(
+                section
+                    .options
+                    .unknown
+                    .keys()
+                    .map(|key| format!("{}{}", prefix, key)),
+            );
+        }
+        keys
+    }
+
+    /// Load `path` if given, otherwise `true-iso.toml` from the working directory if it exists
+    pub fn discover(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None if Path::new(CONFIG_FILE_NAME).is_file() => {
+                Self::load(Path::new(CONFIG_FILE_NAME))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Options for one input: built-in defaults, then the top-level keys, then matching overrides
+    pub fn options_for(&self, input: &Path) -> CorrectionOptions {
+        let mut options = CorrectionOptions::default();
+        self.defaults.apply(&mut options);
+
+        let relative = self.relative_path(input);
+        let match_options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::default()
+        };
+        for section in &self.overrides {
+            if section.pattern.matches_path_with(&relative, match_options) {
+                section.options.apply(&mut options);
+            }
+        }
+        options
+    }
+
+    /// Input path relative to the config root (unchanged if it lies outside of it)
+    fn relative_path(&self, input: &Path) -> PathBuf {
+        let root = if self.root.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            self.root.as_path()
+        };
+        let (Ok(input_abs), Ok(root_abs)) = (std::path::absolute(input), std::path::absolute(root))
+        else {
+            return input.to_path_buf();
+        };
+        input_abs
+            .strip_prefix(&root_abs)
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|_| input.to_path_buf())
+    }
+}
+
+/// Parse a `WxH` pair of positive integers such as `64x32`
+pub(crate) fn parse_dimensions(s: &str) -> std::result::Result<(u32, u32), String> {
+    let (a, b) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("Invalid size '{}', expected NxM", s))?;
+    let parse = |part: &str| {
+        part.parse::<u32>()
+            .ok()
+            .filter(|&value| value > 0)
+            .ok_or_else(|| format!("Invalid size '{}', expected positive integers", s))
+    };
+    Ok((parse(a)?, parse(b)?))
+}
+
+/// Parse an `X,Y` pixel position such as `32,64`
+pub(crate) fn parse_point(s: &str) -> std::result::Result<(u32, u32), String> {
+    let invalid = || format!("Invalid position '{}', expected X,Y", s);
+    let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+    let parse = |part: &str| part.trim().parse::<u32>().map_err(|_| invalid());
+    Ok((parse(x)?, parse(y)?))
+}
+
+fn deserialize_dimensions<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<(u32, u32)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    parse_dimensions(&text)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_point<'de, D>(deserializer: D) -> std::result::Result<Option<(u32, u32)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    parse_point(&text)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_ratio<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<IsometricRatio>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_height<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<HeightPolicy>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_pattern<'de, D>(deserializer: D) -> std::result::Result<Pattern, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    Pattern::new(&text)
+        .map_err(|err| serde::de::Error::custom(format!("invalid glob '{}': {}", text, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        size = 64
+        canvas = "64x96"
+        canvas-anchor = "32, 64"
+        recursive = true
+        split = true
+
+        [[override]]
+        match = "props/**"
+        ratio = "3:1"
+        size = 128
+        anchor = "footprint-center"
+        pixel-art = true
+
+        [[override]]
+        match = "props/tall/*.png"
+        filter = "lanczos3"
+        pixel-art = false
+        vote-threshold = 20
+        detector = "contour"
+        source-ratio = "2:1"
+        left-angle = 30
+        on-low-confidence = "mirror"
+    "#;
+
+    #[test]
+    fn test_overrides_apply_in_order() {
+        let config: ProjectConfig = toml::from_str(CONFIG).unwrap();
+        assert_eq!(config.recursive, Some(true));
+        assert_eq!(config.split, Some(true));
+
+        let terrain = config.options_for(Path::new("terrain/grass.png"));
+        assert_eq!(terrain.size, 64);
+        assert_eq!(terrain.ratio.horizontal, 2.0);
+        assert_eq!(terrain.anchor, AnchorPoint::BottomVertex);
+        assert_eq!(terrain.canvas, Some((64, 96)));
+        assert_eq!(terrain.canvas_anchor, Some((32, 64)));
+
+        let prop = config.options_for(Path::new("props/tall/tree.png"));
+        assert_eq!(prop.size, 128);
+        assert_eq!(prop.ratio.horizontal, 3.0);
+        assert_eq!(prop.anchor, AnchorPoint::FootprintCenter);
+        assert_eq!(prop.detection.vote_threshold, 20);
+        assert_eq!(prop.detection.detector, Detector::Contour);
+        assert_eq!(prop.angles.left, Some(-30.0));
+        assert_eq!(prop.on_low_confidence, LowConfidencePolicy::Mirror);
+        assert_eq!(prop.interpolation, Interpolation::Lanczos3);
+        assert_eq!(terrain.interpolation, Interpolation::Bicubic);
+        assert!(config.options_for(Path::new("props/barrel.png")).pixel_art);
+        assert!(!prop.pixel_art);
+        assert!((prop.angles.right.unwrap() - 26.565).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let config: ProjectConfig = toml::from_str(CONFIG).unwrap();
+        assert!(config.unknown_keys().is_empty());
+
+        let text = "tolerence = 1\n[[override]]\nmatch = \"*.png\"\ntile_width = 64\n";
+        let config: ProjectConfig = toml::from_str(text).unwrap();
+        assert_eq!(
+            config.unknown_keys(),
+            ["tolerence", "override[1].tile_width"]
+        );
+        assert_eq!(config.defaults.tolerance, None);
+    }
+
+    #[test]
+    fn test_invalid_ratio_is_rejected() {
+        let result: std::result::Result<ProjectConfig, _> = toml::from_str("ratio = \"2-1\"");
+        assert!(result.is_err());
+    }
+}
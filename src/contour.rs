@@ -0,0 +1,278 @@
+use image::{GrayImage, Luma, RgbaImage};
+use imageproc::contours::{find_contours, BorderType};
+use tracing::debug;
+
+/// Maximum number of edges fitted to the outline
+///
+/// A flat tile's silhouette is the four-edged top diamond; a block adds two vertical
+/// sides, and its lower edges are parallel to the top diamond's.
+const MAX_EDGES: usize = 6;
+
+/// Random line hypotheses tried per edge
+const RANSAC_ITERATIONS: usize = 512;
+
+/// Contour points closer than this to a line (in pixels) support it
+const RANSAC_DISTANCE: f64 = 1.0;
+
+/// Smallest share of the outline an edge must explain to be accepted
+const MIN_EDGE_FRACTION: f64 = 0.04;
+
+/// Absolute minimum number of contour points on an edge
+const MIN_EDGE_POINTS: usize = 8;
+
+/// Range of absolute slopes (in degrees) accepted as diamond edges
+const SLOPE_RANGE: std::ops::RangeInclusive<f64> = 15.0..=60.0;
+
+/// Smallest absolute angle (in degrees) accepted as a vertical side
+const MIN_VERTICAL_DEGREES: f64 = 75.0;
+
+/// A straight edge fitted to the sprite's silhouette
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContourEdge {
+    /// Direction of the edge in degrees (-90 to 90, negative for left-sloping)
+    pub angle_degrees: f64,
+    /// Direction of the edge's normal in radians
+    pub normal: f64,
+    /// Signed distance of the edge from the origin along its normal
+    pub r: f64,
+    /// Number of contour points on the edge
+    pub support: usize,
+}
+
+/// Points of the largest outer contour of the alpha silhouette, in border order
+pub fn outer_contour(img: &RgbaImage, alpha_threshold: u8) -> Vec<(f64, f64)> {
+    let mask = GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        if img.get_pixel(x, y)[3] >= alpha_threshold {
+            Luma([255])
+        } else {
+            Luma([0])
+        }
+    });
+
+    find_contours::<i32>(&mask)
+        .into_iter()
+        .filter(|contour| contour.border_type == BorderType::Outer)
+        .max_by_key(|contour| contour.points.len())
+        .map(|contour| {
+            contour
+                .points
+                .iter()
+                .map(|p| (p.x as f64, p.y as f64))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Fit up to six sloping or vertical edges to the silhouette's outer contour with sequential RANSAC
+///
+/// Each round samples pairs of contour points, keeps the candidate line supported by the
+/// most points, refines it by a total least squares fit over its inliers and removes
+/// them before looking for the next edge. Sampling is seeded, so results are reproducible.
+pub fn fit_contour_edges(img: &RgbaImage, alpha_threshold: u8) -> Vec<ContourEdge> {
+    let contour = outer_contour(img, alpha_threshold);
+    let min_support = MIN_EDGE_POINTS.max((contour.len() as f64 * MIN_EDGE_FRACTION) as usize);
+
+    debug!(
+        points = contour.len(),
+        min_support, "extracted outer contour"
+    );
+
+    let mut rng = SplitMix64(0x5EED_1507);
+    let mut remaining = contour;
+    let mut edges = Vec::new();
+
+    while edges.len() < MAX_EDGES && remaining.len() >= min_support {
+        let Some(candidate) = best_hypothesis(&remaining, &mut rng) else {
+            break;
+        };
+        let inliers: Vec<(f64, f64)> = remaining
+            .iter()
+            .copied()
+            .filter(|&p| candidate.distance(p) <= RANSAC_DISTANCE)
+            .collect();
+        let Some(line) = Line::fit(&inliers).filter(|line| line.is_candidate()) else {
+            break;
+        };
+
+        let (support, rest): (Vec<_>, Vec<_>) = remaining
+            .into_iter()
+            .partition(|&p| line.distance(p) <= RANSAC_DISTANCE);
+        remaining = rest;
+        if support.len() < min_support {
+            break;
+        }
+
+        let edge = line.to_edge(support.len());
+        debug!(
+            angle = edge.angle_degrees,
+            support = edge.support,
+            "fitted contour edge"
+        );
+        edges.push(edge);
+    }
+
+    edges
+}
+
+/// The candidate line through two contour points supported by the most points
+fn best_hypothesis(points: &[(f64, f64)], rng: &mut SplitMix64) -> Option<Line> {
+    let mut best: Option<(usize, Line)> = None;
+
+    for _ in 0..RANSAC_ITERATIONS {
+        // Contour points are in border order, so nearby indices tend to share an edge
+        let i = rng.below(points.len());
+        let offset = 2 + rng.below((points.len() / 4).max(1));
+        let j = (i + offset) % points.len();
+
+        let Some(line) = Line::through(points[i], points[j]).filter(|line| line.is_candidate())
+        else {
+            continue;
+        };
+        let support = points
+            .iter()
+            .filter(|&&p| line.distance(p) <= RANSAC_DISTANCE)
+            .count();
+        if best.as_ref().is_none_or(|(count, _)| support > *count) {
+            best = Some((support, line));
+        }
+    }
+
+    best.map(|(_, line)| line)
+}
+
+/// Line through `point` along the unit vector `direction`
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    point: (f64, f64),
+    direction: (f64, f64),
+}
+
+impl Line {
+    fn through(a: (f64, f64), b: (f64, f64)) -> Option<Self> {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length = dx.hypot(dy);
+        (length > 0.0).then(|| Self {
+            point: a,
+            direction: (dx / length, dy / length),
+        })
+    }
+
+    /// Total least squares fit: the centroid and principal axis of the points
+    fn fit(points: &[(f64, f64)]) -> Option<Self> {
+        if points.len() < 2 {
+            return None;
+        }
+        let n = points.len() as f64;
+        let cx = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let cy = points.iter().map(|p| p.1).sum::<f64>() / n;
+
+        let (mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0);
+        for &(x, y) in points {
+            sxx += (x - cx) * (x - cx);
+            sxy += (x - cx) * (y - cy);
+            syy += (y - cy) * (y - cy);
+        }
+        let theta = 0.5 * (2.0 * sxy).atan2(sxx - syy);
+
+        Some(Self {
+            point: (cx, cy),
+            direction: (theta.cos(), theta.sin()),
+        })
+    }
+
+    fn distance(&self, p: (f64, f64)) -> f64 {
+        let (dx, dy) = (p.0 - self.point.0, p.1 - self.point.1);
+        (dx * self.direction.1 - dy * self.direction.0).abs()
+    }
+
+    /// Direction in degrees, normalized to -90 to 90
+    fn angle_degrees(&self) -> f64 {
+        let degrees = self.direction.1.atan2(self.direction.0).to_degrees();
+        if degrees > 90.0 {
+            degrees - 180.0
+        } else if degrees < -90.0 {
+            degrees + 180.0
+        } else {
+            degrees
+        }
+    }
+
+    /// Sloping like a diamond edge or close to vertical like a block side
+    fn is_candidate(&self) -> bool {
+        let angle = self.angle_degrees().abs();
+        SLOPE_RANGE.contains(&angle) || angle >= MIN_VERTICAL_DEGREES
+    }
+
+    fn to_edge(self, support: usize) -> ContourEdge {
+        let angle_degrees = self.angle_degrees();
+        let normal = (angle_degrees + 90.0).to_radians();
+        let r = self.point.0 * normal.cos() + self.point.1 * normal.sin();
+        ContourEdge {
+            angle_degrees,
+            normal,
+            r,
+            support,
+        }
+    }
+}
+
+/// Small seeded generator for RANSAC sampling
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform index in `0..n` (`n` must be positive)
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// Opaque 2:1 diamond with a noisy checkerboard interior
+    fn textured_diamond(width: u32) -> RgbaImage {
+        let height = width / 2;
+        let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+        RgbaImage::from_fn(width, height, |x, y| {
+            let dx = (x as f64 + 0.5 - cx).abs() / cx;
+            let dy = (y as f64 + 0.5 - cy).abs() / cy;
+            if dx + dy > 1.0 {
+                Rgba([0, 0, 0, 0])
+            } else if (x / 3 + y / 5) % 2 == 0 {
+                Rgba([40, 120, 30, 255])
+            } else {
+                Rgba([180, 220, 90, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_fits_diamond_edges() {
+        let edges = fit_contour_edges(&textured_diamond(200), 10);
+        assert_eq!(edges.len(), 4);
+        for edge in &edges {
+            assert!(
+                (edge.angle_degrees.abs() - 26.565).abs() < 1.5,
+                "{:?}",
+                edge
+            );
+        }
+        assert_eq!(edges.iter().filter(|e| e.angle_degrees < 0.0).count(), 2);
+    }
+
+    #[test]
+    fn test_empty_image_has_no_edges() {
+        let img = RgbaImage::from_pixel(16, 16, Rgba([0, 0, 0, 0]));
+        assert!(fit_contour_edges(&img, 10).is_empty());
+    }
+}
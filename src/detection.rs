@@ -1,21 +1,108 @@
-use anyhow::{Context, Result};
+use clap::ValueEnum;
 use image::{DynamicImage, GrayImage, RgbaImage};
 use imageproc::edges::canny;
 use imageproc::hough::{detect_lines, LineDetectionOptions, PolarLine};
+use nalgebra::Vector3;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fmt;
+use tracing::debug;
 
-use crate::geometry::DetectedAngles;
+use crate::contour::{fit_contour_edges, outer_contour};
+use crate::error::{Result, TrueIsoError};
+use crate::geometry::{DetectedAngles, DetectedDiamond, Side};
+
+/// Sprite bounding box (x, y, width, height)
+pub type SpriteBounds = (u32, u32, u32, u32);
 
 /// Result of the detection pipeline
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DetectedGeometry {
     /// Detected isometric angles
     pub angles: DetectedAngles,
     /// Bounding box of the sprite (x, y, width, height)
-    pub bounds: (u32, u32, u32, u32),
+    pub bounds: SpriteBounds,
     /// Center point of the sprite
     pub center: (f64, f64),
     /// Number of lines detected
     pub line_count: usize,
+    /// Corners of the top face, if they could be located
+    pub diamond: Option<DetectedDiamond>,
+}
+
+/// How candidate edge lines are found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Detector {
+    /// Hough transform on Canny edges of the whole sprite
+    #[default]
+    Hough,
+    /// RANSAC line fit to the alpha silhouette's outer contour
+    ///
+    /// Ignores the sprite's interior, so busy textures cannot produce false edges.
+    Contour,
+}
+
+impl fmt::Display for Detector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Detector::Hough => write!(f, "hough"),
+            Detector::Contour => write!(f, "contour"),
+        }
+    }
+}
+
+/// Tunable parameters of the detection pipeline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetectionConfig {
+    /// Line detector
+    pub detector: Detector,
+    /// Minimum alpha for a pixel to count as part of the sprite
+    pub alpha_threshold: u8,
+    /// Canny low hysteresis threshold
+    pub canny_low: f32,
+    /// Canny high hysteresis threshold
+    pub canny_high: f32,
+    /// Minimum number of Hough votes for a line
+    pub vote_threshold: u32,
+    /// Non-maximum suppression radius in Hough space
+    pub suppression_radius: u32,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        Self {
+            detector: Detector::Hough,
+            alpha_threshold: 10,
+            canny_low: 30.0,
+            canny_high: 100.0,
+            vote_threshold: 40,
+            suppression_radius: 8,
+        }
+    }
+}
+
+impl DetectionConfig {
+    /// Check that the parameters can be passed to the edge and line detectors
+    pub fn validate(&self) -> Result<()> {
+        if !(self.canny_low >= 0.0 && self.canny_high.is_finite()) {
+            return Err(TrueIsoError::InvalidConfig(
+                "Canny thresholds must be finite and non-negative",
+            ));
+        }
+        if self.canny_low > self.canny_high {
+            return Err(TrueIsoError::InvalidConfig(
+                "Canny low threshold must not exceed the high threshold",
+            ));
+        }
+        if self.vote_threshold == 0 {
+            return Err(TrueIsoError::InvalidConfig(
+                "Hough vote threshold must be positive",
+            ));
+        }
+        Ok(())
+    }
 }
 
 /// A detected line with its properties
@@ -23,10 +110,14 @@ pub struct DetectedGeometry {
 struct DetectedLine {
     angle_degrees: f64,
     length: f64,
+    /// Distance of the line from the origin (Hough `r`)
+    r: f64,
+    /// Direction of the line's normal in radians (Hough angle)
+    normal: f64,
 }
 
 /// Find the non-transparent bounding box of a sprite
-pub fn find_sprite_bounds(img: &RgbaImage, alpha_threshold: u8) -> Option<(u32, u32, u32, u32)> {
+pub fn find_sprite_bounds(img: &RgbaImage, alpha_threshold: u8) -> Option<SpriteBounds> {
     let (width, height) = img.dimensions();
     let mut min_x = width;
     let mut max_x = 0;
@@ -52,26 +143,199 @@ pub fn find_sprite_bounds(img: &RgbaImage, alpha_threshold: u8) -> Option<(u32,
     }
 }
 
+/// Sprite bounding box and its center, failing if the image is fully transparent
+pub fn locate_sprite(img: &RgbaImage, alpha_threshold: u8) -> Result<(SpriteBounds, (f64, f64))> {
+    let bounds = find_sprite_bounds(img, alpha_threshold)
+        .ok_or(TrueIsoError::EmptySprite { alpha_threshold })?;
+
+    let center = (
+        bounds.0 as f64 + bounds.2 as f64 / 2.0,
+        bounds.1 as f64 + bounds.3 as f64 / 2.0,
+    );
+    Ok((bounds, center))
+}
+
+/// Locate the top-face rhombus for the given edge angles
+///
+/// The upper edges are the lines at the left and right angles that touch the silhouette
+/// from above. The left and right vertices lie on them at the sprite's horizontal extent,
+/// and the bottom vertex completes the parallelogram. Returns `None` if the edges do not
+/// meet between the two vertices.
+pub fn fit_diamond(
+    img: &RgbaImage,
+    alpha_threshold: u8,
+    bounds: SpriteBounds,
+    angles: &DetectedAngles,
+) -> Option<DetectedDiamond> {
+    let left_slope = angles.left_angle.to_radians().tan();
+    let right_slope = angles.right_angle.to_radians().tan();
+    if !(left_slope < 0.0 && right_slope > 0.0) {
+        return None;
+    }
+
+    // Intercepts of the lines y = slope * x + c resting on the topmost opaque pixel of
+    // each column, measured at the pixel corner that reaches furthest over the edge
+    let (x0, y0, width, height) = bounds;
+    let mut left_intercept = f64::INFINITY;
+    let mut right_intercept = f64::INFINITY;
+    for x in x0..x0 + width {
+        let Some(y) = (y0..y0 + height).find(|&y| img.get_pixel(x, y)[3] >= alpha_threshold) else {
+            continue;
+        };
+        let (x, y) = (x as f64, y as f64);
+        left_intercept = left_intercept.min(y - left_slope * x);
+        right_intercept = right_intercept.min(y - right_slope * (x + 1.0));
+    }
+    if !left_intercept.is_finite() {
+        return None;
+    }
+
+    let top_x = (left_intercept - right_intercept) / (right_slope - left_slope);
+    let (left_x, right_x) = (x0 as f64, (x0 + width) as f64);
+    if !(left_x..=right_x).contains(&top_x) {
+        return None;
+    }
+
+    let left = (left_x, left_slope * left_x + left_intercept);
+    let top = (top_x, left_slope * top_x + left_intercept);
+    let right = (right_x, right_slope * right_x + right_intercept);
+    let bottom = (left.0 + right.0 - top.0, left.1 + right.1 - top.1);
+    Some(DetectedDiamond {
+        left,
+        top,
+        right,
+        bottom,
+    })
+}
+
+/// Share of each profile half ignored at both ends when fitting edges (rounded vertices)
+const PROFILE_TRIM: f64 = 0.1;
+
+/// Profile points further than this from a first fit (in pixels) are dropped before refitting
+const PROFILE_OUTLIER_DISTANCE: f64 = 2.0;
+
+/// Locate the top-face quadrilateral without assuming parallel edges
+///
+/// Each outline edge is fitted separately: the upper edges to the topmost opaque pixel of
+/// each column, the lower edges to the bottommost one. The top, left and right vertices
+/// follow as for [`fit_diamond`]. The bottom vertex is hidden on blocks, so it is found
+/// through vanishing points: the near-left edge runs from the left vertex towards the
+/// point where the top-right and lower-left edges meet (they are parallel in the scene),
+/// and likewise on the right. On flat tiles this reduces to the visible bottom vertex.
+pub fn fit_perspective_diamond(
+    img: &RgbaImage,
+    alpha_threshold: u8,
+    bounds: SpriteBounds,
+) -> Option<DetectedDiamond> {
+    let (x0, y0, width, height) = bounds;
+    let columns: Vec<(f64, f64, f64)> = (x0..x0 + width)
+        .filter_map(|x| {
+            let opaque = |&y: &u32| img.get_pixel(x, y)[3] >= alpha_threshold;
+            let top = (y0..y0 + height).find(opaque)?;
+            let bottom = (y0..y0 + height).rev().find(opaque)?;
+            Some((x as f64 + 0.5, top as f64, bottom as f64 + 1.0))
+        })
+        .collect();
+
+    let top_profile: Vec<(f64, f64)> = columns.iter().map(|&(x, top, _)| (x, top)).collect();
+    let bottom_profile: Vec<(f64, f64)> =
+        columns.iter().map(|&(x, _, bottom)| (x, bottom)).collect();
+    let apex = top_profile
+        .iter()
+        .enumerate()
+        .min_by(|a, b| a.1 .1.total_cmp(&b.1 .1))?
+        .0;
+    let nadir = bottom_profile
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1 .1.total_cmp(&b.1 .1))?
+        .0;
+
+    let top_left = fit_profile_line(&top_profile[..=apex])?;
+    let top_right = fit_profile_line(&top_profile[apex..])?;
+    let bottom_left = fit_profile_line(&bottom_profile[..=nadir])?;
+    let bottom_right = fit_profile_line(&bottom_profile[nadir..])?;
+    let left_side = Vector3::new(1.0, 0.0, -(x0 as f64));
+    let right_side = Vector3::new(1.0, 0.0, -((x0 + width) as f64));
+
+    let top = top_left.cross(&top_right);
+    let left = top_left.cross(&left_side);
+    let right = top_right.cross(&right_side);
+    let near_left = left.cross(&top_right.cross(&bottom_left));
+    let near_right = right.cross(&top_left.cross(&bottom_right));
+    let bottom = near_left.cross(&near_right);
+
+    let point = |p: Vector3<f64>| (p.z.abs() > f64::EPSILON).then(|| (p.x / p.z, p.y / p.z));
+    let diamond = DetectedDiamond {
+        left: point(left)?,
+        top: point(top)?,
+        right: point(right)?,
+        bottom: point(bottom)?,
+    };
+
+    let inside = |p: (f64, f64)| diamond.left.0 < p.0 && p.0 < diamond.right.0;
+    let convex = inside(diamond.top)
+        && inside(diamond.bottom)
+        && diamond.top.1 < diamond.left.1.min(diamond.right.1)
+        && diamond.bottom.1 > diamond.left.1.max(diamond.right.1);
+    convex.then_some(diamond)
+}
+
+/// Least-squares line `a·x + b·y + c = 0` through the middle of a profile segment
+///
+/// Points far from a first fit (e.g. a chimney on a roof edge) are dropped before refitting.
+fn fit_profile_line(points: &[(f64, f64)]) -> Option<Vector3<f64>> {
+    let trim = (points.len() as f64 * PROFILE_TRIM) as usize;
+    let middle = &points[trim..points.len() - trim];
+
+    let fit = |points: &[(f64, f64)]| -> Option<(f64, f64)> {
+        if points.len() < 2 {
+            return None;
+        }
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+        (sxx > 0.0).then(|| {
+            let slope = sxy / sxx;
+            (slope, mean_y - slope * mean_x)
+        })
+    };
+
+    let (slope, intercept) = fit(middle)?;
+    let inliers: Vec<(f64, f64)> = middle
+        .iter()
+        .copied()
+        .filter(|&(x, y)| (slope * x + intercept - y).abs() <= PROFILE_OUTLIER_DISTANCE)
+        .collect();
+    let (slope, intercept) = fit(&inliers)?;
+    Some(Vector3::new(slope, -1.0, intercept))
+}
+
 /// Convert RGBA image to grayscale, using alpha to mask out transparent pixels
 fn to_grayscale_masked(img: &RgbaImage, alpha_threshold: u8) -> GrayImage {
     let (width, height) = img.dimensions();
     let mut gray = GrayImage::new(width, height);
+    if width == 0 {
+        return gray;
+    }
 
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = img.get_pixel(x, y);
-            if pixel[3] >= alpha_threshold {
-                // Standard luminance conversion
-                let luma = (0.299 * pixel[0] as f64
-                    + 0.587 * pixel[1] as f64
-                    + 0.114 * pixel[2] as f64) as u8;
-                gray.put_pixel(x, y, image::Luma([luma]));
-            } else {
-                // Transparent pixels become white (background)
-                gray.put_pixel(x, y, image::Luma([255]));
+    let src_row_len = width as usize * 4;
+    gray.par_chunks_mut(width as usize)
+        .zip(img.as_raw().par_chunks(src_row_len))
+        .for_each(|(gray_row, src_row)| {
+            for (luma_out, pixel) in gray_row.iter_mut().zip(src_row.chunks_exact(4)) {
+                *luma_out = if pixel[3] >= alpha_threshold {
+                    // Standard luminance conversion
+                    (0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64)
+                        as u8
+                } else {
+                    // Transparent pixels become white (background)
+                    255
+                };
             }
-        }
-    }
+        });
 
     gray
 }
@@ -140,146 +404,428 @@ fn estimate_line_length(edges: &GrayImage, line: &PolarLine) -> f64 {
     count as f64
 }
 
-/// Classify lines into left-sloping and right-sloping groups
-fn classify_lines(lines: &[DetectedLine]) -> (Vec<&DetectedLine>, Vec<&DetectedLine>) {
-    let mut left_sloping = Vec::new(); // Negative angles (-60° to -15°)
-    let mut right_sloping = Vec::new(); // Positive angles (15° to 60°)
+/// Lines grouped by the kind of isometric edge they can belong to
+struct ClassifiedLines<'a> {
+    /// Negative angles (-60° to -15°)
+    left: Vec<&'a DetectedLine>,
+    /// Positive angles (15° to 60°)
+    right: Vec<&'a DetectedLine>,
+    /// Within 15° of vertical (walls and block sides)
+    vertical: Vec<&'a DetectedLine>,
+}
+
+/// Classify lines into left-sloping, right-sloping and vertical groups
+fn classify_lines(lines: &[DetectedLine]) -> ClassifiedLines<'_> {
+    let mut classified = ClassifiedLines {
+        left: Vec::new(),
+        right: Vec::new(),
+        vertical: Vec::new(),
+    };
 
     for line in lines {
         let angle = line.angle_degrees;
-        if angle >= -60.0 && angle <= -15.0 {
-            left_sloping.push(line);
-        } else if angle >= 15.0 && angle <= 60.0 {
-            right_sloping.push(line);
+        if (-60.0..=-15.0).contains(&angle) {
+            classified.left.push(line);
+        } else if (15.0..=60.0).contains(&angle) {
+            classified.right.push(line);
+        } else if angle.abs() >= 75.0 {
+            classified.vertical.push(line);
         }
-        // Lines outside these ranges are ignored (horizontal/vertical)
+        // Remaining lines are ignored (near-horizontal or in between)
     }
 
-    (left_sloping, right_sloping)
+    classified
 }
 
-/// Compute weighted median of angles
-fn weighted_median(lines: &[&DetectedLine]) -> Option<(f64, f64)> {
-    if lines.is_empty() {
-        return None;
+/// Lean of a near-vertical line from upright in degrees (positive when its top leans right)
+fn tilt_from_vertical(line: &DetectedLine) -> f64 {
+    if line.angle_degrees > 0.0 {
+        line.angle_degrees - 90.0
+    } else {
+        line.angle_degrees + 90.0
     }
+}
+
+/// Weighted median tilt of the vertical lines, if they agree well enough to be trusted
+fn estimate_vertical(lines: &[&DetectedLine]) -> Option<f64> {
+    let tilts: Vec<(f64, f64)> = lines
+        .iter()
+        .map(|l| (tilt_from_vertical(l), l.length))
+        .collect();
+    let tilt = weighted_median(&tilts)?;
+    let deviations: Vec<(f64, f64)> = tilts
+        .iter()
+        .map(|&(t, weight)| ((t - tilt).abs(), weight))
+        .collect();
+    let spread = weighted_median(&deviations)?;
+
+    let agreement = agreement_score(spread);
+    debug!(
+        tilt,
+        spread,
+        agreement,
+        lines = lines.len(),
+        "vertical edges"
+    );
+    (agreement >= MIN_VERTICAL_AGREEMENT).then_some(tilt)
+}
+
+/// Spread (in degrees) of lines scattered uniformly over a side's 45° classification
+/// window, i.e. the median absolute deviation of pure noise
+const NOISE_SPREAD_DEGREES: f64 = 11.25;
+
+/// Vertical lines must agree at least this well to constrain the correction
+const MIN_VERTICAL_AGREEMENT: f64 = 0.5;
+
+/// Lines within this many degrees of a side's median angle support that angle
+///
+/// Hough angles come in 1° steps and the outlines of painted sprites wobble by a few
+/// degrees, so a narrower band misses most of their edges.
+const INLIER_DEGREES: f64 = 5.0;
+
+/// Silhouette pixels closer than this fraction of the sprite width to a supporting line
+/// count as explained
+///
+/// Painted sprites have dark outlines several pixels thick, and the Hough transform often
+/// finds their inner edge rather than the silhouette; 1° angle steps add a few pixels of
+/// drift along long edges. Both scale with the sprite, so the band does too.
+const INLIER_DISTANCE: f64 = 0.01;
+
+/// Lower bound of the inlier band in pixels, for small sprites
+const MIN_INLIER_DISTANCE: f64 = 1.5;
+
+/// ...and only if the outline there is within this many degrees of the line's direction
+const INLIER_NORMAL_DEGREES: f64 = 15.0;
+
+/// Contour points on either side of a boundary pixel used for the outline's direction
+const CONTOUR_SPAN: usize = 5;
 
-    let total_weight: f64 = lines.iter().map(|l| l.length).sum();
-    if total_weight == 0.0 {
+/// Left/right magnitude difference (in degrees) at which symmetry drops to 0.5
+const SYMMETRY_SCALE_DEGREES: f64 = 10.0;
+
+/// Weighted median of `(value, weight)` pairs
+fn weighted_median(values: &[(f64, f64)]) -> Option<f64> {
+    let total_weight: f64 = values.iter().map(|&(_, weight)| weight).sum();
+    if total_weight <= 0.0 {
         return None;
     }
 
-    // Sort by angle
-    let mut sorted: Vec<_> = lines.iter().map(|l| (l.angle_degrees, l.length)).collect();
+    let mut sorted = values.to_vec();
     sorted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
 
-    // Find weighted median
-    let mut cumulative = 0.0;
     let half_weight = total_weight / 2.0;
-
-    for (angle, weight) in &sorted {
+    let mut cumulative = 0.0;
+    for (value, weight) in &sorted {
         cumulative += weight;
         if cumulative >= half_weight {
-            let confidence = total_weight / (lines.len() as f64 * 100.0);
-            return Some((*angle, confidence.min(1.0)));
+            return Some(*value);
         }
     }
+    sorted.last().map(|&(value, _)| value)
+}
+
+/// Robust angle estimate for one side of the diamond
+#[derive(Debug)]
+struct SideEstimate<'a> {
+    /// Weighted median angle in degrees
+    angle: f64,
+    /// Weighted median absolute deviation from `angle` in degrees
+    spread: f64,
+    /// Lines close to the median angle
+    inliers: Vec<&'a DetectedLine>,
+}
+
+/// Estimate a side's angle as the weighted median of its lines
+///
+/// Each line is weighted by the silhouette pixels it explains, so lines through interior
+/// texture (bricks, weaves, circuit traces) cannot outvote the outline. Lengths are used
+/// instead when no line touches the silhouette.
+fn estimate_side<'a>(
+    lines: &[&'a DetectedLine],
+    boundary: &[BoundaryPixel],
+    sprite_width: u32,
+) -> Option<SideEstimate<'a>> {
+    let max_distance = inlier_distance(sprite_width);
+    let support: Vec<f64> = lines
+        .iter()
+        .map(|line| {
+            let explained = boundary
+                .iter()
+                .filter(|pixel| pixel.explained_by(line, max_distance));
+            explained.count() as f64
+        })
+        .collect();
+    let weights: Vec<f64> = if support.iter().any(|&s| s > 0.0) {
+        support
+    } else {
+        lines.iter().map(|l| l.length).collect()
+    };
+
+    let angles: Vec<(f64, f64)> = lines
+        .iter()
+        .zip(&weights)
+        .map(|(l, &weight)| (l.angle_degrees, weight))
+        .collect();
+    let angle = weighted_median(&angles)?;
+
+    let deviations: Vec<(f64, f64)> = lines
+        .iter()
+        .zip(&weights)
+        .map(|(l, &weight)| ((l.angle_degrees - angle).abs(), weight))
+        .collect();
+    let spread = weighted_median(&deviations)?;
+
+    let inliers = lines
+        .iter()
+        .copied()
+        .filter(|l| (l.angle_degrees - angle).abs() <= INLIER_DEGREES)
+        .collect();
+
+    Some(SideEstimate {
+        angle,
+        spread,
+        inliers,
+    })
+}
+
+/// Distance in pixels within which a line explains a silhouette pixel
+fn inlier_distance(sprite_width: u32) -> f64 {
+    (sprite_width as f64 * INLIER_DISTANCE).max(MIN_INLIER_DISTANCE)
+}
+
+/// Pixel on the edge of the sprite's silhouette
+#[derive(Debug, Clone, Copy)]
+struct BoundaryPixel {
+    x: f64,
+    y: f64,
+    /// Direction of the outline's normal in radians
+    normal: f64,
+}
+
+impl BoundaryPixel {
+    /// Whether the pixel lies on `line` and the silhouette runs along it there
+    fn explained_by(&self, line: &DetectedLine, max_distance: f64) -> bool {
+        let distance = self.x * line.normal.cos() + self.y * line.normal.sin() - line.r;
+        let turn = (self.normal - line.normal).rem_euclid(PI);
+        distance.abs() <= max_distance && turn.min(PI - turn) <= INLIER_NORMAL_DEGREES.to_radians()
+    }
+}
+
+/// Points of the silhouette's outer contour with the direction of the outline there
+///
+/// Holes and detached specks are left out; the direction is taken over a few neighbouring
+/// contour points, so single-pixel steps of a sloping edge do not disturb it.
+fn silhouette_boundary(img: &RgbaImage, alpha_threshold: u8) -> Vec<BoundaryPixel> {
+    let contour = outer_contour(img, alpha_threshold);
+    let n = contour.len();
+    (0..n)
+        .filter_map(|i| {
+            let (x0, y0) = contour[(i + n - CONTOUR_SPAN % n) % n];
+            let (x1, y1) = contour[(i + CONTOUR_SPAN) % n];
+            let (x, y) = contour[i];
+            (x0 != x1 || y0 != y1).then(|| BoundaryPixel {
+                x,
+                y,
+                normal: f64::atan2(x0 - x1, y1 - y0),
+            })
+        })
+        .collect()
+}
+
+/// How well the lines agree on one angle: 1.0 for no spread, 0.0 for random lines
+fn agreement_score(spread: f64) -> f64 {
+    (1.0 - spread / NOISE_SPREAD_DEGREES).clamp(0.0, 1.0)
+}
+
+/// Fraction of the side's expected silhouette edges explained by its supporting lines
+///
+/// Each side of a diamond spans the sprite width horizontally (two parallel edges of
+/// half the width each), and a shallow silhouette edge has about one boundary pixel per
+/// column, so the expected count is the sprite width. This keeps the score independent
+/// of the sprite's resolution.
+fn coverage_score(inliers: &[&DetectedLine], boundary: &[BoundaryPixel], sprite_width: u32) -> f64 {
+    if sprite_width == 0 {
+        return 0.0;
+    }
+
+    let max_distance = inlier_distance(sprite_width);
+    let explained = boundary
+        .iter()
+        .filter(|pixel| {
+            inliers
+                .iter()
+                .any(|line| pixel.explained_by(line, max_distance))
+        })
+        .count();
 
-    // Fallback to simple weighted average
-    let weighted_sum: f64 = lines.iter().map(|l| l.angle_degrees * l.length).sum();
-    let avg_angle = weighted_sum / total_weight;
-    let confidence = total_weight / (lines.len() as f64 * 100.0);
+    (explained as f64 / sprite_width as f64).min(1.0)
+}
 
-    Some((avg_angle, confidence.min(1.0)))
+/// How close the two sides' magnitudes are: 1.0 when symmetric, 0.5 at `SYMMETRY_SCALE_DEGREES`
+fn symmetry_score(left_angle: f64, right_angle: f64) -> f64 {
+    let difference = left_angle.abs() - right_angle.abs();
+    1.0 / (1.0 + (difference / SYMMETRY_SCALE_DEGREES).powi(2))
 }
 
 /// Main detection function: analyze an image to find isometric angles
-pub fn detect_isometric_angles(img: &DynamicImage, verbose: bool) -> Result<DetectedGeometry> {
+pub fn detect_isometric_angles(
+    img: &DynamicImage,
+    config: &DetectionConfig,
+) -> Result<DetectedGeometry> {
+    config.validate()?;
     let rgba = img.to_rgba8();
 
-    // Find sprite bounds
-    let bounds = find_sprite_bounds(&rgba, 10)
-        .context("Could not find sprite bounds - image may be fully transparent")?;
+    let (bounds, center) = locate_sprite(&rgba, config.alpha_threshold)?;
 
-    let center = (
-        bounds.0 as f64 + bounds.2 as f64 / 2.0,
-        bounds.1 as f64 + bounds.3 as f64 / 2.0,
+    debug!(
+        x = bounds.0,
+        y = bounds.1,
+        width = bounds.2,
+        height = bounds.3,
+        center_x = center.0,
+        center_y = center.1,
+        "sprite bounds"
     );
 
-    if verbose {
-        eprintln!("Sprite bounds: {:?}", bounds);
-        eprintln!("Sprite center: ({:.1}, {:.1})", center.0, center.1);
+    let detected_lines = match config.detector {
+        Detector::Hough => hough_lines(&rgba, config),
+        Detector::Contour => contour_lines(&rgba, config),
+    };
+
+    // Classify into left sloping, right sloping and vertical
+    let classified = classify_lines(&detected_lines);
+    let (left_lines, right_lines) = (&classified.left, &classified.right);
+
+    debug!(
+        left = left_lines.len(),
+        right = right_lines.len(),
+        vertical = classified.vertical.len(),
+        "classified lines"
+    );
+
+    // Compute robust angle estimates. A side without usable lines mirrors the other
+    // one with zero confidence; the pipeline's low-confidence policy decides what to do.
+    let boundary = silhouette_boundary(&rgba, config.alpha_threshold);
+    let left = estimate_side(left_lines, &boundary, bounds.2);
+    let right = estimate_side(right_lines, &boundary, bounds.2);
+    let (left_angle, right_angle) = match (&left, &right) {
+        (Some(left), Some(right)) => (left.angle, right.angle),
+        (Some(left), None) => (left.angle, -left.angle),
+        (None, Some(right)) => (-right.angle, right.angle),
+        (None, None) => return Err(TrueIsoError::NoEdgesDetected { side: Side::Both }),
+    };
+
+    // Confidence = agreement of the side's lines x share of the silhouette they explain
+    // x left/right symmetry; all three are independent of the sprite's resolution
+    let symmetry = match (&left, &right) {
+        (Some(_), Some(_)) => symmetry_score(left_angle, right_angle),
+        _ => 1.0,
+    };
+    let side_confidence = |estimate: &Option<SideEstimate>, name: &str| {
+        estimate.as_ref().map_or(0.0, |estimate| {
+            let agreement = agreement_score(estimate.spread);
+            let coverage = coverage_score(&estimate.inliers, &boundary, bounds.2);
+            debug!(
+                side = name,
+                spread = estimate.spread,
+                agreement,
+                coverage,
+                symmetry,
+                "confidence components"
+            );
+            agreement * coverage * symmetry
+        })
+    };
+    let left_conf = side_confidence(&left, "left");
+    let right_conf = side_confidence(&right, "right");
+
+    debug!(angle = left_angle, confidence = left_conf, "left angle");
+    debug!(angle = right_angle, confidence = right_conf, "right angle");
+
+    let mut angles = DetectedAngles::new(left_angle, right_angle, left_conf, right_conf);
+    angles.vertical_tilt = estimate_vertical(&classified.vertical);
+    let diamond = fit_diamond(&rgba, config.alpha_threshold, bounds, &angles);
+
+    if let Some(diamond) = &diamond {
+        debug!(
+            left = ?diamond.left,
+            top = ?diamond.top,
+            right = ?diamond.right,
+            bottom = ?diamond.bottom,
+            width = diamond.width(),
+            "top diamond"
+        );
     }
 
+    Ok(DetectedGeometry {
+        angles,
+        bounds,
+        center,
+        line_count: detected_lines.len(),
+        diamond,
+    })
+}
+
+/// Candidate lines from a Hough transform of the sprite's Canny edges
+fn hough_lines(rgba: &RgbaImage, config: &DetectionConfig) -> Vec<DetectedLine> {
     // Convert to grayscale with alpha masking
-    let gray = to_grayscale_masked(&rgba, 10);
+    let gray = to_grayscale_masked(rgba, config.alpha_threshold);
 
-    // Edge detection with adaptive thresholds
-    let edges = detect_edges(&gray, 30.0, 100.0);
+    // Edge detection
+    let edges = detect_edges(&gray, config.canny_low, config.canny_high);
 
-    if verbose {
-        eprintln!("Applied Canny edge detection (30.0, 100.0)");
-    }
+    debug!(
+        low = config.canny_low,
+        high = config.canny_high,
+        "applied Canny edge detection"
+    );
 
     // Hough line detection
     let options = LineDetectionOptions {
-        vote_threshold: 40,
-        suppression_radius: 8,
+        vote_threshold: config.vote_threshold,
+        suppression_radius: config.suppression_radius,
     };
 
     let polar_lines = detect_lines(&edges, options);
 
-    if verbose {
-        eprintln!("Detected {} Hough lines", polar_lines.len());
-    }
+    debug!(
+        lines = polar_lines.len(),
+        vote_threshold = config.vote_threshold,
+        suppression_radius = config.suppression_radius,
+        "detected Hough lines"
+    );
 
     // Convert to our line representation with estimated lengths
-    let detected_lines: Vec<DetectedLine> = polar_lines
+    polar_lines
         .iter()
         .map(|pl| {
             let angle_degrees = polar_to_angle_degrees(pl);
             let length = estimate_line_length(&edges, pl);
-            DetectedLine { angle_degrees, length }
+            DetectedLine {
+                angle_degrees,
+                length,
+                r: pl.r as f64,
+                normal: (pl.angle_in_degrees as f64).to_radians(),
+            }
         })
-        .collect();
-
-    // Classify into left and right sloping
-    let (left_lines, right_lines) = classify_lines(&detected_lines);
-
-    if verbose {
-        eprintln!(
-            "Classified: {} left-sloping, {} right-sloping lines",
-            left_lines.len(),
-            right_lines.len()
-        );
-    }
-
-    // Compute robust angle estimates
-    let (left_angle, left_conf) = weighted_median(&left_lines)
-        .unwrap_or((-26.565, 0.0)); // Default to ideal if not found
-
-    let (right_angle, right_conf) = weighted_median(&right_lines)
-        .unwrap_or((26.565, 0.0)); // Default to ideal if not found
+        .collect()
+}
 
-    if verbose {
-        eprintln!(
-            "Left angle: {:.2}° (confidence: {:.2})",
-            left_angle, left_conf
-        );
-        eprintln!(
-            "Right angle: {:.2}° (confidence: {:.2})",
-            right_angle, right_conf
-        );
-    }
+/// Candidate lines fitted to the silhouette's outer contour, weighted by their support
+fn contour_lines(rgba: &RgbaImage, config: &DetectionConfig) -> Vec<DetectedLine> {
+    let edges = fit_contour_edges(rgba, config.alpha_threshold);
 
-    let angles = DetectedAngles::new(left_angle, right_angle, left_conf, right_conf);
+    debug!(lines = edges.len(), "fitted contour edges");
 
-    Ok(DetectedGeometry {
-        angles,
-        bounds,
-        center,
-        line_count: polar_lines.len(),
-    })
+    edges
+        .into_iter()
+        .map(|edge| DetectedLine {
+            angle_degrees: edge.angle_degrees,
+            length: edge.support as f64,
+            r: edge.r,
+            normal: edge.normal,
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -293,6 +839,252 @@ mod tests {
         assert!(find_sprite_bounds(&img, 10).is_none());
     }
 
+    #[test]
+    fn test_detect_empty_sprite() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 0])));
+        let result = detect_isometric_angles(&img, &DetectionConfig::default());
+        assert!(matches!(result, Err(TrueIsoError::EmptySprite { .. })));
+    }
+
+    #[test]
+    fn test_invalid_canny_thresholds() {
+        let config = DetectionConfig {
+            canny_low: 120.0,
+            canny_high: 60.0,
+            ..DetectionConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(TrueIsoError::InvalidConfig(_))
+        ));
+        assert!(DetectionConfig::default().validate().is_ok());
+    }
+
+    /// Opaque 2:1 diamond of the given width on a transparent canvas
+    fn diamond(width: u32) -> DynamicImage {
+        let height = width / 2;
+        let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+        let img = RgbaImage::from_fn(width + 8, height + 8, |x, y| {
+            let dx = (x as f64 - 4.0 + 0.5 - cx).abs() / cx;
+            let dy = (y as f64 - 4.0 + 0.5 - cy).abs() / cy;
+            if dx + dy <= 1.0 {
+                Rgba([200, 160, 90, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        DynamicImage::ImageRgba8(img)
+    }
+
+    /// Opaque 2:1 block: a diamond of the given width on top of vertical sides
+    fn block(width: u32, wall: u32) -> DynamicImage {
+        let half = width as f64 / 2.0;
+        let quarter = width as f64 / 4.0;
+        let img = RgbaImage::from_fn(width + 8, width / 2 + wall + 8, |x, y| {
+            let (px, py) = (x as f64 - 4.0 + 0.5, y as f64 - 4.0 + 0.5);
+            let dx = (px - half).abs();
+            let top = quarter - dx / 2.0;
+            let bottom = quarter + wall as f64 + dx / 2.0;
+            if dx <= half && py >= top && py <= bottom {
+                Rgba([90, 70, 50, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        DynamicImage::ImageRgba8(img)
+    }
+
+    #[test]
+    fn test_vertical_sides_are_detected() {
+        for detector in [Detector::Hough, Detector::Contour] {
+            let config = DetectionConfig {
+                detector,
+                ..DetectionConfig::default()
+            };
+            let geometry = detect_isometric_angles(&block(200, 90), &config).unwrap();
+            let tilt = geometry.angles.vertical_tilt.expect("vertical sides");
+            assert!(tilt.abs() < 1.0, "{}: {}", detector, tilt);
+        }
+    }
+
+    #[test]
+    fn test_weighted_median() {
+        assert_eq!(
+            weighted_median(&[(1.0, 1.0), (5.0, 1.0), (9.0, 10.0)]),
+            Some(9.0)
+        );
+        assert_eq!(
+            weighted_median(&[(3.0, 2.0), (1.0, 1.0), (2.0, 1.0)]),
+            Some(2.0)
+        );
+        assert_eq!(weighted_median(&[(1.0, 0.0)]), None);
+    }
+
+    #[test]
+    fn test_agreement_decreases_with_spread() {
+        assert_eq!(agreement_score(0.0), 1.0);
+        assert!(agreement_score(2.0) > agreement_score(6.0));
+        assert_eq!(agreement_score(NOISE_SPREAD_DEGREES), 0.0);
+    }
+
+    #[test]
+    fn test_confidence_is_resolution_independent() {
+        let config = DetectionConfig::default();
+        let small = detect_isometric_angles(&diamond(128), &config).unwrap();
+        let large = detect_isometric_angles(&diamond(1024), &config).unwrap();
+
+        for geometry in [&small, &large] {
+            assert!((geometry.angles.right_angle - 26.565).abs() < 1.5);
+            assert!(geometry.angles.left_confidence > 0.5);
+            assert!(geometry.angles.right_confidence > 0.5);
+        }
+        assert!((small.angles.right_confidence - large.angles.right_confidence).abs() < 0.3);
+    }
+
+    /// Opaque convex polygon (corners in clockwise screen order) on a transparent canvas;
+    /// `jitter(x, y, edge)` moves the outline of each edge outward by that many pixels
+    fn polygon(
+        width: u32,
+        height: u32,
+        corners: &[(f64, f64)],
+        jitter: impl Fn(u32, u32, usize) -> f64,
+    ) -> DynamicImage {
+        let img = RgbaImage::from_fn(width, height, |x, y| {
+            let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+            let inside = (0..corners.len()).all(|i| {
+                let (ax, ay) = corners[i];
+                let (bx, by) = corners[(i + 1) % corners.len()];
+                let distance =
+                    ((bx - ax) * (py - ay) - (by - ay) * (px - ax)) / (bx - ax).hypot(by - ay);
+                distance >= -jitter(x, y, i)
+            });
+            if inside {
+                Rgba([120, 90, 60, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        DynamicImage::ImageRgba8(img)
+    }
+
+    /// Corners of a 256x128 diamond, left vertex first
+    const DIAMOND: [(f64, f64); 4] = [(4.0, 68.0), (132.0, 4.0), (260.0, 68.0), (132.0, 132.0)];
+
+    #[test]
+    fn test_clean_diamond_is_confident() {
+        let img = polygon(264, 136, &DIAMOND, |_, _, _| 0.0);
+        let angles = detect_isometric_angles(&img, &DetectionConfig::default())
+            .unwrap()
+            .angles;
+
+        // Straight edges agree, explain the whole silhouette and are symmetric
+        assert!(angles.left_confidence > 0.9, "{:?}", angles);
+        assert!(angles.right_confidence > 0.9, "{:?}", angles);
+        assert_eq!(symmetry_score(-26.0, 26.0), 1.0);
+        assert!((symmetry_score(-20.0, 30.0) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_noisy_side_loses_confidence() {
+        // Ragged top-left and bottom-right edges (the left-sloping side), in 4 px steps
+        let img = polygon(264, 136, &DIAMOND, |x, y, edge| {
+            let noise = ((x / 4 * 7919 + y / 4 * 104729) % 97) as f64 / 96.0 - 0.5;
+            if edge % 2 == 0 {
+                8.0 * noise
+            } else {
+                0.0
+            }
+        });
+        let angles = detect_isometric_angles(&img, &DetectionConfig::default())
+            .unwrap()
+            .angles;
+
+        // Less agreement and coverage on the noisy side only, which is still usable
+        assert!((angles.left_angle + 26.565).abs() < 1.5, "{:?}", angles);
+        assert!(angles.left_confidence < 0.8, "{:?}", angles);
+        assert!(angles.left_confidence > 0.1, "{:?}", angles);
+        assert!(angles.right_confidence > 0.9, "{:?}", angles);
+    }
+
+    #[test]
+    fn test_one_sided_sprite_mirrors_its_side() {
+        // A rising band: two parallel edges of the left side and two vertical ends
+        let band = [(4.0, 68.0), (132.0, 4.0), (132.0, 44.0), (4.0, 108.0)];
+        let img = polygon(140, 112, &band, |_, _, _| 0.0);
+        let angles = detect_isometric_angles(&img, &DetectionConfig::default())
+            .unwrap()
+            .angles;
+
+        // The missing side mirrors the other with zero confidence; symmetry does not
+        // penalize the side that was found
+        assert!(angles.left_confidence > 0.9, "{:?}", angles);
+        assert_eq!(angles.right_confidence, 0.0);
+        assert_eq!(angles.right_angle, -angles.left_angle);
+    }
+
+    #[test]
+    fn test_contour_detector() {
+        let config = DetectionConfig {
+            detector: Detector::Contour,
+            ..DetectionConfig::default()
+        };
+        let geometry = detect_isometric_angles(&diamond(256), &config).unwrap();
+        assert!((geometry.angles.left_angle + 26.565).abs() < 1.0);
+        assert!((geometry.angles.right_angle - 26.565).abs() < 1.0);
+        assert!(geometry.angles.left_confidence > 0.5);
+        assert!(geometry.angles.right_confidence > 0.5);
+    }
+
+    #[test]
+    fn test_fit_diamond_corners() {
+        let img = diamond(128).to_rgba8();
+        let (bounds, _) = locate_sprite(&img, 10).unwrap();
+        let angles = DetectedAngles::new(-26.565, 26.565, 1.0, 1.0);
+        let corners = fit_diamond(&img, 10, bounds, &angles).unwrap();
+
+        assert_eq!(corners.width(), bounds.2 as f64);
+        assert!((corners.top.0 - 68.0).abs() < 1.0, "{:?}", corners);
+        assert!((corners.top.1 - 4.0).abs() < 1.0, "{:?}", corners);
+        assert!(
+            (corners.height() - corners.width() / 2.0).abs() < 0.5,
+            "{:?}",
+            corners
+        );
+        let parallel = DetectedAngles::new(20.0, 20.0, 1.0, 1.0);
+        assert!(fit_diamond(&img, 10, bounds, &parallel).is_none());
+    }
+
+    #[test]
+    fn test_fit_perspective_diamond() {
+        // Foreshortened tile: the far edges are shallower than the near ones
+        let corners = [(10.0, 50.0), (70.0, 28.0), (130.0, 50.0), (70.0, 84.0)];
+        let img = RgbaImage::from_fn(140, 95, |x, y| {
+            let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+            let inside = (0..4).all(|i| {
+                let (ax, ay) = corners[i];
+                let (bx, by) = corners[(i + 1) % 4];
+                (bx - ax) * (py - ay) - (by - ay) * (px - ax) >= 0.0
+            });
+            if inside {
+                Rgba([120, 90, 60, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        let (bounds, _) = locate_sprite(&img, 10).unwrap();
+        let diamond = fit_perspective_diamond(&img, 10, bounds).unwrap();
+
+        for (found, expected) in [
+            (diamond.left, corners[0]),
+            (diamond.top, corners[1]),
+            (diamond.right, corners[2]),
+            (diamond.bottom, corners[3]),
+        ] {
+            assert!((found.0 - expected.0).abs() < 2.0, "{:?}", diamond);
+            assert!((found.1 - expected.1).abs() < 2.0, "{:?}", diamond);
+        }
+    }
+
     #[test]
     fn test_find_bounds_full() {
         let img = RgbaImage::from_pixel(10, 10, Rgba([255, 255, 255, 255]));
@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+use crate::geometry::Side;
+
+/// Errors produced by the true-iso library
+#[derive(Debug, Error)]
+pub enum TrueIsoError {
+    /// No pixel reaches the alpha threshold
+    #[error("image is fully transparent (no pixels with alpha >= {alpha_threshold})")]
+    EmptySprite { alpha_threshold: u8 },
+
+    /// Line detection found no isometric edges on the given side
+    #[error("no isometric edges detected on the {side} side")]
+    NoEdgesDetected { side: Side },
+
+    /// An angle was detected, but with too little confidence to act on
+    #[error("low confidence on the {side} side ({confidence:.2} < {min_confidence:.2})")]
+    LowConfidence {
+        side: Side,
+        confidence: f64,
+        min_confidence: f64,
+    },
+
+    /// The detected axes or the correction matrix are singular
+    #[error("degenerate transform: {0}")]
+    DegenerateTransform(&'static str),
+
+    /// Detection or correction parameters are inconsistent
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(&'static str),
+
+    /// The sprites of an atlas do not fit the maximum atlas size
+    #[error("{frames} sprites do not fit a {max_size}x{max_size} atlas")]
+    AtlasTooSmall { frames: usize, max_size: u32 },
+
+    /// Two sprites of an atlas have the same frame name
+    #[error("duplicate atlas frame '{name}'")]
+    DuplicateFrame { name: String },
+
+    /// A project configuration file could not be parsed
+    #[error("invalid config {path:?}: {source}")]
+    Config {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+
+    /// A project configuration file contains keys that are not options
+    #[error("unknown keys in config {path:?}: {}", keys.join(", "))]
+    UnknownConfigKeys { path: PathBuf, keys: Vec<String> },
+
+    /// A path given as input does not exist and is not a glob pattern
+    #[error("input not found: {0:?}")]
+    InputNotFound(PathBuf),
+
+    /// A glob pattern could not be parsed
+    #[error("invalid glob pattern '{pattern}': {source}")]
+    InvalidPattern {
+        pattern: String,
+        #[source]
+        source: glob::PatternError,
+    },
+
+    /// Reading a file or directory failed
+    #[error("I/O error on {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// Decoding or encoding an image failed
+    #[error("image error on {path:?}: {source}")]
+    Image {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+}
+
+/// Result type used throughout the library
+pub type Result<T> = std::result::Result<T, TrueIsoError>;
@@ -1,4 +1,9 @@
-use nalgebra::{Matrix3, Vector2};
+use nalgebra::{DMatrix, DVector, Matrix2, Matrix3, SMatrix, SVector, Vector2};
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Result, TrueIsoError};
 
 /// Represents an isometric projection ratio (horizontal:vertical)
 /// For standard 2:1 isometric, this means 2 pixels horizontal per 1 pixel vertical
@@ -10,7 +15,10 @@ pub struct IsometricRatio {
 
 impl IsometricRatio {
     pub fn new(horizontal: f64, vertical: f64) -> Self {
-        Self { horizontal, vertical }
+        Self {
+            horizontal,
+            vertical,
+        }
     }
 
     /// Returns the target angle in radians for this ratio
@@ -25,14 +33,111 @@ impl IsometricRatio {
     }
 }
 
+impl FromStr for IsometricRatio {
+    type Err = String;
+
+    /// Parse an `H:V` ratio such as `2:1`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 2 {
+            return Err(format!("Invalid ratio format '{}', expected N:M", s));
+        }
+
+        let horizontal: f64 = parts[0]
+            .parse()
+            .map_err(|_| format!("Invalid horizontal value: {}", parts[0]))?;
+        let vertical: f64 = parts[1]
+            .parse()
+            .map_err(|_| format!("Invalid vertical value: {}", parts[1]))?;
+
+        if horizontal <= 0.0 || vertical <= 0.0 {
+            return Err("Ratio values must be positive".to_string());
+        }
+
+        Ok(Self::new(horizontal, vertical))
+    }
+}
+
 impl Default for IsometricRatio {
     fn default() -> Self {
         Self::new(2.0, 1.0)
     }
 }
 
+/// How tall the walls of a block end up relative to its corrected footprint
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HeightPolicy {
+    /// Keep the walls' pixel height
+    #[default]
+    Preserve,
+    /// Walls as tall as a footprint edge under the target projection
+    Cube,
+    /// Walls this many footprint edges tall
+    Ratio(f64),
+}
+
+impl HeightPolicy {
+    /// Wall height in pixels for a footprint of `width` under `ratio`, or `None` to preserve it
+    pub fn wall_height(&self, ratio: &IsometricRatio, width: f64) -> Option<f64> {
+        let edge = (width / 2.0).hypot(width / 2.0 * ratio.vertical / ratio.horizontal);
+        match *self {
+            HeightPolicy::Preserve => None,
+            HeightPolicy::Cube => Some(edge),
+            HeightPolicy::Ratio(factor) => Some(factor * edge),
+        }
+    }
+}
+
+impl FromStr for HeightPolicy {
+    type Err = String;
+
+    /// Parse `preserve` or `cube` (explicit ratios are given as numbers elsewhere)
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(HeightPolicy::Preserve),
+            "cube" => Ok(HeightPolicy::Cube),
+            _ => Err(format!(
+                "Invalid height policy '{}', expected preserve or cube",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HeightPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightPolicy::Preserve => write!(f, "preserve"),
+            HeightPolicy::Cube => write!(f, "cube"),
+            HeightPolicy::Ratio(factor) => write!(f, "{}", factor),
+        }
+    }
+}
+
+/// Side of the isometric diamond an edge belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    /// Left-sloping edges (negative angles)
+    Left,
+    /// Right-sloping edges (positive angles)
+    Right,
+    /// Both sides at once
+    Both,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Left => write!(f, "left"),
+            Side::Right => write!(f, "right"),
+            Side::Both => write!(f, "left and right"),
+        }
+    }
+}
+
 /// Detected angles from the isometric sprite
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DetectedAngles {
     /// Left-sloping angle (negative, typically around -26.565° for correct iso)
     pub left_angle: f64,
@@ -42,85 +147,355 @@ pub struct DetectedAngles {
     pub left_confidence: f64,
     /// Confidence in the right angle detection (0.0 to 1.0)
     pub right_confidence: f64,
+    /// Lean of vertical edges from upright in degrees (positive when the top leans right),
+    /// if consistent vertical edges were found
+    pub vertical_tilt: Option<f64>,
 }
 
 impl DetectedAngles {
-    pub fn new(left_angle: f64, right_angle: f64, left_confidence: f64, right_confidence: f64) -> Self {
+    pub fn new(
+        left_angle: f64,
+        right_angle: f64,
+        left_confidence: f64,
+        right_confidence: f64,
+    ) -> Self {
         Self {
             left_angle,
             right_angle,
             left_confidence,
             right_confidence,
+            vertical_tilt: None,
         }
     }
 
-    /// Check if the detected angles are close to the target
-    pub fn is_close_to_target(&self, target: &IsometricRatio, tolerance_degrees: f64) -> bool {
+    /// Absolute deviation of each side from the target angle in degrees (left, right)
+    pub fn deviation_from(&self, target: &IsometricRatio) -> (f64, f64) {
         let target_angle = target.target_angle_degrees();
         let left_diff = (self.left_angle.abs() - target_angle).abs();
         let right_diff = (self.right_angle - target_angle).abs();
+        (left_diff, right_diff)
+    }
+
+    /// Split the angles into a rotation of the whole sprite and a symmetric slope
+    pub fn decompose(&self) -> AngleDecomposition {
+        AngleDecomposition {
+            rotation: (self.left_angle + self.right_angle) / 2.0,
+            slope: (self.right_angle - self.left_angle) / 2.0,
+        }
+    }
+
+    /// Check if the detected angles are close to the target
+    pub fn is_close_to_target(&self, target: &IsometricRatio, tolerance_degrees: f64) -> bool {
+        let (left_diff, right_diff) = self.deviation_from(target);
         left_diff < tolerance_degrees && right_diff < tolerance_degrees
     }
 }
 
+/// Detected angles as a global rotation plus the slope error of a level sprite
+///
+/// A sprite rotated by `rotation` degrees has axes at `rotation - slope` and
+/// `rotation + slope`, so the asymmetry between the sides is the rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AngleDecomposition {
+    /// Rotation of the whole sprite in degrees (positive is clockwise on screen)
+    pub rotation: f64,
+    /// Slope of both axes once the rotation is removed, in degrees
+    pub slope: f64,
+}
+
+impl AngleDecomposition {
+    /// Signed difference between the level slope and the target angle in degrees
+    pub fn slope_error(&self, target: &IsometricRatio) -> f64 {
+        self.slope - target.target_angle_degrees()
+    }
+}
+
+/// Corners of the sprite's top-face rhombus in image coordinates
+///
+/// The corners always form a parallelogram; on blocks the bottom vertex is hidden
+/// behind the side faces and completes the shape from the other three.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DetectedDiamond {
+    pub left: (f64, f64),
+    pub top: (f64, f64),
+    pub right: (f64, f64),
+    pub bottom: (f64, f64),
+}
+
+impl DetectedDiamond {
+    /// Footprint width: horizontal distance between the left and right vertices
+    pub fn width(&self) -> f64 {
+        self.right.0 - self.left.0
+    }
+
+    /// Footprint height: vertical distance between the top and bottom vertices
+    pub fn height(&self) -> f64 {
+        self.bottom.1 - self.top.1
+    }
+
+    /// Midpoint of the left and right vertices (where the diagonals cross for a parallelogram)
+    pub fn center(&self) -> (f64, f64) {
+        (
+            (self.left.0 + self.right.0) / 2.0,
+            (self.left.1 + self.right.1) / 2.0,
+        )
+    }
+
+    /// Footprint width rounded to a whole number of grid tiles (at least one)
+    pub fn snapped_width(&self, tile_width: f64) -> f64 {
+        (self.width() / tile_width).round().max(1.0) * tile_width
+    }
+
+    /// Largest deviation of any of the four edges from the target angle in degrees
+    pub fn max_deviation_from(&self, target: &IsometricRatio) -> f64 {
+        let target_angle = target.target_angle_degrees();
+        [
+            (self.left, self.top),
+            (self.top, self.right),
+            (self.left, self.bottom),
+            (self.bottom, self.right),
+        ]
+        .iter()
+        .map(|&(a, b)| {
+            let angle = (b.1 - a.1).abs().atan2((b.0 - a.0).abs()).to_degrees();
+            (angle - target_angle).abs()
+        })
+        .fold(0.0, f64::max)
+    }
+
+    /// The ideal diamond of `ratio` with the given footprint width, sharing this one's center
+    pub fn target(&self, ratio: &IsometricRatio, width: f64) -> Self {
+        let (cx, cy) = self.center();
+        let half_width = width / 2.0;
+        let half_height = half_width * ratio.vertical / ratio.horizontal;
+        Self {
+            left: (cx - half_width, cy),
+            top: (cx, cy - half_height),
+            right: (cx + half_width, cy),
+            bottom: (cx, cy + half_height),
+        }
+    }
+}
+
+/// Manually specified angles that replace detection on one or both sides
+///
+/// The sign of each angle is normalized: left is always negative, right always positive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct AngleOverride {
+    /// Left-sloping angle in degrees
+    pub left: Option<f64>,
+    /// Right-sloping angle in degrees
+    pub right: Option<f64>,
+}
+
+impl AngleOverride {
+    pub fn new(left: Option<f64>, right: Option<f64>) -> Self {
+        Self {
+            left: left.map(|angle| -angle.abs()),
+            right: right.map(f64::abs),
+        }
+    }
+
+    /// Both angles of a known source projection (e.g. tiles drawn at 3:1)
+    pub fn from_ratio(ratio: &IsometricRatio) -> Self {
+        let angle = ratio.target_angle_degrees();
+        Self::new(Some(angle), Some(angle))
+    }
+
+    /// True if no side is overridden
+    pub fn is_empty(&self) -> bool {
+        self.left.is_none() && self.right.is_none()
+    }
+
+    /// True if both sides are overridden, so no line detection is needed
+    pub fn is_complete(&self) -> bool {
+        self.left.is_some() && self.right.is_some()
+    }
+
+    /// Replace the overridden sides, with full confidence
+    pub fn apply(&self, angles: &mut DetectedAngles) {
+        if let Some(left) = self.left {
+            angles.left_angle = left;
+            angles.left_confidence = 1.0;
+        }
+        if let Some(right) = self.right {
+            angles.right_angle = right;
+            angles.right_confidence = 1.0;
+        }
+    }
+}
+
 /// Compute the affine correction matrix to transform from detected angles to target angles
 ///
-/// The transform is computed as: M = B_target × B_current⁻¹
-/// where B represents the basis formed by the isometric axes
+/// The angles are decomposed into a rotation and a symmetric slope, and the transform
+/// first rotates the sprite level, then scales its axes to the target slope:
+/// M = S × R(−rotation). This equals B_target × B_current⁻¹ for the bases formed by the
+/// isometric axes, but never shears a merely rotated sprite.
+///
+/// With a detected diamond, the transform is centered on the diamond instead of `center`
+/// and scaled uniformly so the footprint keeps its width; the corners do not change the
+/// rotation or the slope.
+///
+/// If vertical edges were detected, the vertical axis becomes a third basis constraint:
+/// the unit upright vector must map to an upright vector `vertical_scale` long (1 keeps
+/// the walls' height). That over-determines the matrix, so it is solved by least squares,
+/// trading a little of the diagonal fit for upright walls of the requested height when
+/// the constraints conflict.
+/// Fails if the detected axes are parallel (B_current is singular).
 pub fn compute_correction_matrix(
     detected: &DetectedAngles,
     target: &IsometricRatio,
     center: (f64, f64),
-) -> Matrix3<f64> {
+    diamond: Option<&DetectedDiamond>,
+    vertical_scale: f64,
+) -> Result<Matrix3<f64>> {
     let target_angle = target.target_angle();
+    let AngleDecomposition { rotation, slope } = detected.decompose();
+    let (rotation, slope) = (rotation.to_radians(), slope.to_radians());
 
-    // Current basis vectors (from detected angles)
-    let left_rad = detected.left_angle.to_radians();
-    let right_rad = detected.right_angle.to_radians();
+    // Axes at 0° or 90° slope collapse onto one line
+    if (2.0 * slope).sin().abs() < 1e-9 {
+        return Err(TrueIsoError::DegenerateTransform(
+            "detected isometric axes are parallel",
+        ));
+    }
 
-    // Unit vectors along the detected isometric axes
-    let current_left = Vector2::new(left_rad.cos(), left_rad.sin());
-    let current_right = Vector2::new(right_rad.cos(), right_rad.sin());
+    // Level the sprite, then stretch the symmetric axes (cos ±slope, sin ±slope) onto
+    // (cos ±target, sin ±target)
+    #[rustfmt::skip]
+    let level = Matrix2::new(
+        rotation.cos(), rotation.sin(),
+        -rotation.sin(), rotation.cos(),
+    );
+    #[rustfmt::skip]
+    let stretch = Matrix2::new(
+        target_angle.cos() / slope.cos(), 0.0,
+        0.0, target_angle.sin() / slope.sin(),
+    );
 
-    // Target basis vectors (for perfect 2:1 isometric)
-    // Left axis goes up-left (negative angle), right axis goes up-right (positive angle)
-    let target_left = Vector2::new((-target_angle).cos(), (-target_angle).sin());
-    let target_right = Vector2::new(target_angle.cos(), target_angle.sin());
+    // The diamond's left and right vertices end up as far apart horizontally as they are now
+    let (center, scale) = match diamond {
+        None => (center, 1.0),
+        Some(diamond) => {
+            let span = Vector2::new(
+                diamond.right.0 - diamond.left.0,
+                diamond.right.1 - diamond.left.1,
+            );
+            let width = (stretch * level * span).x;
+            if width < 1e-9 || diamond.width() < 1e-9 {
+                return Err(TrueIsoError::DegenerateTransform(
+                    "diamond corners are collinear",
+                ));
+            }
+            (diamond.center(), diamond.width() / width)
+        }
+    };
 
-    // Build 2x2 basis matrices
-    // B_current maps from iso-space to image-space
-    let b_current = nalgebra::Matrix2::from_columns(&[current_left, current_right]);
-    let b_target = nalgebra::Matrix2::from_columns(&[target_left, target_right]);
+    let upright = detected.vertical_tilt.map(|tilt| {
+        let tilt = tilt.to_radians();
+        Vector2::new(tilt.sin(), -tilt.cos())
+    });
+    let skew = match upright {
+        None => stretch * scale,
+        Some(upright) => {
+            // On the leveled sprite: S · (cos ±slope, sin ±slope) = scale · (cos ±target,
+            // sin ±target) for both axes, plus S · upright = (0, -vertical_scale)
+            let upright = level * upright;
+            let mut a = DMatrix::zeros(6, 4);
+            let mut b = DVector::zeros(6);
+            for (i, sign) in [-1.0, 1.0].into_iter().enumerate() {
+                let current = [slope.cos(), sign * slope.sin()];
+                a.view_mut((2 * i, 0), (1, 2)).copy_from_slice(&current);
+                a.view_mut((2 * i + 1, 2), (1, 2)).copy_from_slice(&current);
+                b[2 * i] = scale * target_angle.cos();
+                b[2 * i + 1] = scale * sign * target_angle.sin();
+            }
+            a.view_mut((4, 0), (1, 2))
+                .copy_from_slice(&[upright.x, upright.y]);
+            a.view_mut((5, 2), (1, 2))
+                .copy_from_slice(&[upright.x, upright.y]);
+            b[5] = -vertical_scale;
 
-    // Compute the transformation: M = B_target × B_current⁻¹
-    let transform_2x2 = match b_current.try_inverse() {
-        Some(inv) => b_target * inv,
-        None => nalgebra::Matrix2::identity(), // Fallback if singular
+            let m = least_squares(a, b, "detected isometric axes are parallel")?;
+            Matrix2::new(m[0], m[1], m[2], m[3])
+        }
     };
+    let transform_2x2 = skew * level;
 
     // Build full 3x3 affine matrix with translation to center
     let (cx, cy) = center;
 
     // Translate to origin, apply transform, translate back
+    #[rustfmt::skip]
     let translate_to_origin = Matrix3::new(
         1.0, 0.0, -cx,
         0.0, 1.0, -cy,
         0.0, 0.0, 1.0,
     );
 
+    #[rustfmt::skip]
     let transform = Matrix3::new(
         transform_2x2[(0, 0)], transform_2x2[(0, 1)], 0.0,
         transform_2x2[(1, 0)], transform_2x2[(1, 1)], 0.0,
         0.0, 0.0, 1.0,
     );
 
+    #[rustfmt::skip]
     let translate_back = Matrix3::new(
         1.0, 0.0, cx,
         0.0, 1.0, cy,
         0.0, 0.0, 1.0,
     );
 
-    translate_back * transform * translate_to_origin
+    Ok(translate_back * transform * translate_to_origin)
+}
+
+/// Compute the homography mapping the four diamond corners onto the ideal diamond
+///
+/// The target diamond shares the detected one's center and footprint width, so
+/// foreshortened far edges are stretched back to the length of the near ones.
+pub fn compute_perspective_matrix(
+    diamond: &DetectedDiamond,
+    target: &IsometricRatio,
+) -> Result<Matrix3<f64>> {
+    let ideal = diamond.target(target, diamond.width());
+    let from = [diamond.left, diamond.top, diamond.right, diamond.bottom];
+    let to = [ideal.left, ideal.top, ideal.right, ideal.bottom];
+
+    // Direct linear transform with h33 = 1: two equations per correspondence
+    let mut a = SMatrix::<f64, 8, 8>::zeros();
+    let mut b = SVector::<f64, 8>::zeros();
+    for (i, (&(x, y), &(u, v))) in from.iter().zip(&to).enumerate() {
+        let (r, s) = (2 * i, 2 * i + 1);
+        a.row_mut(r)
+            .copy_from_slice(&[x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y]);
+        a.row_mut(s)
+            .copy_from_slice(&[0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y]);
+        b[r] = u;
+        b[s] = v;
+    }
+
+    let h = a
+        .lu()
+        .solve(&b)
+        .filter(|h| h.iter().all(|value| value.is_finite()))
+        .ok_or(TrueIsoError::DegenerateTransform(
+            "diamond corners do not form a quadrilateral",
+        ))?;
+    Ok(Matrix3::new(
+        h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], 1.0,
+    ))
+}
+
+/// Least-squares solution of `a · x = b`, failing if `a` does not have full column rank
+fn least_squares(a: DMatrix<f64>, b: DVector<f64>, reason: &'static str) -> Result<DVector<f64>> {
+    let svd = a.svd(true, true);
+    let max = svd.singular_values.max();
+    if svd.singular_values.min() <= max * 1e-9 {
+        return Err(TrueIsoError::DegenerateTransform(reason));
+    }
+    svd.solve(&b, 0.0)
+        .map_err(|_| TrueIsoError::DegenerateTransform(reason))
 }
 
 /// Transform a point using the affine matrix
@@ -148,10 +523,22 @@ pub fn compute_output_bounds(
         .map(|&(x, y)| transform_point(matrix, x, y))
         .collect();
 
-    let min_x = transformed.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
-    let max_x = transformed.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
-    let min_y = transformed.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
-    let max_y = transformed.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+    let min_x = transformed
+        .iter()
+        .map(|p| p.0)
+        .fold(f64::INFINITY, f64::min);
+    let max_x = transformed
+        .iter()
+        .map(|p| p.0)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let min_y = transformed
+        .iter()
+        .map(|p| p.1)
+        .fold(f64::INFINITY, f64::min);
+    let max_y = transformed
+        .iter()
+        .map(|p| p.1)
+        .fold(f64::NEG_INFINITY, f64::max);
 
     let new_width = (max_x - min_x).ceil() as u32;
     let new_height = (max_y - min_y).ceil() as u32;
@@ -170,15 +557,214 @@ mod tests {
         assert!((angle - 26.565).abs() < 0.01);
     }
 
+    #[test]
+    fn test_parse_ratio() {
+        let ratio: IsometricRatio = "3:1".parse().unwrap();
+        assert_eq!((ratio.horizontal, ratio.vertical), (3.0, 1.0));
+        assert!("2".parse::<IsometricRatio>().is_err());
+        assert!("0:1".parse::<IsometricRatio>().is_err());
+    }
+
+    #[test]
+    fn test_deviation_from_target() {
+        let detected = DetectedAngles::new(-24.565, 30.565, 1.0, 1.0);
+        let target = IsometricRatio::new(2.0, 1.0);
+        let (left, right) = detected.deviation_from(&target);
+        assert!((left - 2.0).abs() < 0.01);
+        assert!((right - 4.0).abs() < 0.01);
+        assert!(!detected.is_close_to_target(&target, 3.0));
+        assert!(detected.is_close_to_target(&target, 4.5));
+    }
+
+    #[test]
+    fn test_angle_override_one_side() {
+        let mut angles = DetectedAngles::new(-30.0, 31.0, 0.4, 0.5);
+        let manual = AngleOverride::new(Some(24.0), None);
+        manual.apply(&mut angles);
+
+        assert_eq!(angles.left_angle, -24.0);
+        assert_eq!(angles.left_confidence, 1.0);
+        assert_eq!(angles.right_angle, 31.0);
+        assert!(!manual.is_complete());
+        assert!(AngleOverride::from_ratio(&IsometricRatio::new(3.0, 1.0)).is_complete());
+    }
+
     #[test]
     fn test_identity_transform() {
         let detected = DetectedAngles::new(-26.565, 26.565, 1.0, 1.0);
         let target = IsometricRatio::new(2.0, 1.0);
-        let matrix = compute_correction_matrix(&detected, &target, (50.0, 50.0));
+        let matrix =
+            compute_correction_matrix(&detected, &target, (50.0, 50.0), None, 1.0).unwrap();
 
         // Should be close to identity since detected ≈ target
         let (x, y) = transform_point(&matrix, 50.0, 50.0);
         assert!((x - 50.0).abs() < 0.1);
         assert!((y - 50.0).abs() < 0.1);
     }
+
+    #[test]
+    fn test_rotated_sprite_is_rotated_back() {
+        let detected = DetectedAngles::new(-24.0, 29.0, 1.0, 1.0);
+        let decomposition = detected.decompose();
+        assert!((decomposition.rotation - 2.5).abs() < 1e-9);
+        assert!((decomposition.slope - 26.5).abs() < 1e-9);
+
+        // A level 2:1 sprite rotated by 3°: the correction is a pure rotation
+        let rotated = DetectedAngles::new(-26.565 + 3.0, 26.565 + 3.0, 1.0, 1.0);
+        let target = IsometricRatio::new(2.0, 1.0);
+        assert!(rotated.decompose().slope_error(&target).abs() < 1e-3);
+        let m = compute_correction_matrix(&rotated, &target, (0.0, 0.0), None, 1.0).unwrap();
+        let (x, y) = transform_point(&m, 100.0, 0.0);
+        assert!((x.hypot(y) - 100.0).abs() < 0.01);
+        assert!((y.atan2(x).to_degrees() + 3.0).abs() < 0.01);
+        let (x, y) = transform_point(&m, 0.0, 100.0);
+        assert!((x.hypot(y) - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_diamond_sets_center_and_footprint_width() {
+        // A 3:1 diamond rotated by 3° about (50, 50), detected with matching angles
+        let (sin, cos) = 3.0_f64.to_radians().sin_cos();
+        let rotate = |(x, y): (f64, f64)| (50.0 + x * cos - y * sin, 50.0 + x * sin + y * cos);
+        let diamond = DetectedDiamond {
+            left: rotate((-45.0, 0.0)),
+            top: rotate((0.0, -15.0)),
+            right: rotate((45.0, 0.0)),
+            bottom: rotate((0.0, 15.0)),
+        };
+        let target = IsometricRatio::new(2.0, 1.0);
+        let slope = (1.0_f64 / 3.0).atan().to_degrees();
+        let detected = DetectedAngles::new(3.0 - slope, 3.0 + slope, 1.0, 1.0);
+        let matrix =
+            compute_correction_matrix(&detected, &target, (0.0, 0.0), Some(&diamond), 1.0).unwrap();
+
+        // Rotated back and stretched about the diamond's center, keeping its width
+        let ideal = diamond.target(&target, diamond.width());
+        for (from, to) in [
+            (diamond.left, ideal.left),
+            (diamond.top, ideal.top),
+            (diamond.right, ideal.right),
+            (diamond.bottom, ideal.bottom),
+        ] {
+            let (x, y) = transform_point(&matrix, from.0, from.1);
+            assert!((x - to.0).abs() < 1e-9 && (y - to.1).abs() < 1e-9);
+        }
+
+        // A diamond that is only rotated is rotated back and scaled uniformly, never sheared
+        let slope = 0.5_f64.atan().to_degrees();
+        let detected = DetectedAngles::new(3.0 - slope, 3.0 + slope, 1.0, 1.0);
+        let m =
+            compute_correction_matrix(&detected, &target, (0.0, 0.0), Some(&diamond), 1.0).unwrap();
+        assert!((m[(0, 0)] - m[(1, 1)]).abs() < 1e-9);
+        assert!((m[(0, 1)] + m[(1, 0)]).abs() < 1e-9);
+        assert!((m[(1, 0)].atan2(m[(0, 0)]).to_degrees() + 3.0).abs() < 1e-9);
+        let (x, y) = transform_point(&m, 50.0, 50.0);
+        assert!((x - 50.0).abs() < 1e-9 && (y - 50.0).abs() < 1e-9);
+
+        assert_eq!(diamond.snapped_width(32.0), 96.0);
+        assert_eq!(diamond.snapped_width(128.0), 128.0);
+    }
+
+    #[test]
+    fn test_perspective_matrix_maps_all_corners() {
+        // Far edges foreshortened: the top vertex sits lower than in a parallelogram
+        let diamond = DetectedDiamond {
+            left: (0.0, 40.0),
+            top: (50.0, 22.0),
+            right: (100.0, 40.0),
+            bottom: (50.0, 65.0),
+        };
+        let target = IsometricRatio::new(2.0, 1.0);
+        assert!(diamond.max_deviation_from(&target) > 4.0);
+
+        let matrix = compute_perspective_matrix(&diamond, &target).unwrap();
+        let ideal = diamond.target(&target, 100.0);
+        for (from, to) in [
+            (diamond.left, ideal.left),
+            (diamond.top, ideal.top),
+            (diamond.right, ideal.right),
+            (diamond.bottom, ideal.bottom),
+        ] {
+            let (x, y) = transform_point(&matrix, from.0, from.1);
+            assert!((x - to.0).abs() < 1e-6 && (y - to.1).abs() < 1e-6);
+        }
+        assert!(ideal.max_deviation_from(&target) < 1e-9);
+    }
+
+    #[test]
+    fn test_vertical_edges_stay_upright() {
+        // Right axis too steep: fixing it alone would shear the walls
+        let mut detected = DetectedAngles::new(-26.565, 32.0, 1.0, 1.0);
+        let target = IsometricRatio::new(2.0, 1.0);
+        let sheared = compute_correction_matrix(&detected, &target, (0.0, 0.0), None, 1.0).unwrap();
+        let lean = |m: &Matrix3<f64>| {
+            let (x, y) = transform_point(m, 0.0, -100.0);
+            (x / y).atan().to_degrees().abs()
+        };
+        assert!(lean(&sheared) > 1.0);
+
+        detected.vertical_tilt = Some(0.0);
+        let upright = compute_correction_matrix(&detected, &target, (0.0, 0.0), None, 1.0).unwrap();
+        assert!(lean(&upright) < 0.5 * lean(&sheared));
+
+        let diamond = DetectedDiamond {
+            left: (0.0, 30.0),
+            top: (40.0, 0.0),
+            right: (100.0, 30.0),
+            bottom: (60.0, 60.0),
+        };
+        let fitted =
+            compute_correction_matrix(&detected, &target, (0.0, 0.0), Some(&diamond), 1.0).unwrap();
+        let unconstrained = compute_correction_matrix(
+            &DetectedAngles::new(-36.9, 26.6, 1.0, 1.0),
+            &target,
+            (0.0, 0.0),
+            Some(&diamond),
+            1.0,
+        )
+        .unwrap();
+        assert!(lean(&fitted) < lean(&unconstrained));
+    }
+
+    #[test]
+    fn test_vertical_scale_sets_wall_height() {
+        // A 3:1 footprint stretched to 2:1 would stretch the walls by the same factor
+        let mut detected = DetectedAngles::new(-18.435, 18.435, 1.0, 1.0);
+        detected.vertical_tilt = Some(0.0);
+        let target = IsometricRatio::new(2.0, 1.0);
+        let diamond = DetectedDiamond {
+            left: (0.0, 15.0),
+            top: (45.0, 0.0),
+            right: (90.0, 15.0),
+            bottom: (45.0, 30.0),
+        };
+        let wall = |scale: f64| {
+            let m =
+                compute_correction_matrix(&detected, &target, (0.0, 0.0), Some(&diamond), scale)
+                    .unwrap();
+            let (_, top) = transform_point(&m, 45.0, 30.0);
+            let (_, bottom) = transform_point(&m, 45.0, 70.0);
+            (bottom - top) / 40.0
+        };
+        assert!(wall(1.0) < 1.2);
+        assert!(wall(1.0) < wall(1.5) && wall(1.5) < wall(2.0));
+
+        assert_eq!(HeightPolicy::Preserve.wall_height(&target, 64.0), None);
+        let cube = HeightPolicy::Cube.wall_height(&target, 64.0).unwrap();
+        assert!((cube - 35.777).abs() < 0.01);
+        assert_eq!(
+            HeightPolicy::Ratio(0.5).wall_height(&target, 64.0),
+            Some(cube * 0.5)
+        );
+        assert_eq!("cube".parse::<HeightPolicy>(), Ok(HeightPolicy::Cube));
+        assert!("tall".parse::<HeightPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_parallel_axes_are_degenerate() {
+        let detected = DetectedAngles::new(30.0, 30.0, 1.0, 1.0);
+        let target = IsometricRatio::new(2.0, 1.0);
+        let result = compute_correction_matrix(&detected, &target, (50.0, 50.0), None, 1.0);
+        assert!(matches!(result, Err(TrueIsoError::DegenerateTransform(_))));
+    }
 }
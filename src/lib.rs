@@ -1,9 +1,34 @@
+pub mod atlas;
+pub mod batch;
 pub mod cli;
+pub mod config;
+pub mod contour;
 pub mod detection;
+pub mod error;
 pub mod geometry;
+pub mod pipeline;
+pub mod pixel_art;
+pub mod report;
+pub mod segment;
 pub mod transform;
 
+pub use atlas::{pack_atlas, Atlas, AtlasFrame, AtlasSprite, MaxRectsPacker};
 pub use cli::Cli;
-pub use detection::{detect_isometric_angles, DetectedGeometry};
-pub use geometry::{compute_correction_matrix, IsometricRatio};
-pub use transform::{apply_affine_transform, crop_to_content, resize_to_fit};
+pub use config::{ProjectConfig, CONFIG_FILE_NAME};
+pub use detection::{detect_isometric_angles, DetectedGeometry, DetectionConfig, Detector};
+pub use error::{Result, TrueIsoError};
+pub use geometry::{
+    compute_correction_matrix, compute_perspective_matrix, AngleDecomposition, AngleOverride,
+    DetectedDiamond, HeightPolicy, IsometricRatio, Side,
+};
+pub use pipeline::{
+    load_image, Analysis, Anchor, AnchorPoint, CorrectionMode, CorrectionOptions,
+    CorrectionPipeline, CorrectionReport, HeightSkipped, LowConfidence, LowConfidencePolicy,
+};
+pub use pixel_art::{enforce_stair_steps, integer_scale, snap_to_palette, Palette, Scale2x};
+pub use report::{write_anchor_file, write_report, AnchorRecord, CorrectionRecord, FileRecord};
+pub use segment::{pack_grid, slice_grid, split_sprites, GridLayout, Sprite};
+pub use transform::{
+    apply_affine_transform, apply_projective_transform, crop_to_content, place_on_canvas,
+    resize_by, resize_to_fit, Interpolation, Interpolator,
+};
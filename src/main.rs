@@ -1,152 +1,565 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
-use image::ImageReader;
+use image::{DynamicImage, RgbaImage};
+use rayon::prelude::*;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tracing::{debug_span, error, info, warn};
 
+use true_iso::batch::{
+    anchor_file_path, collect_inputs, find_collision, AnalysisSummary, BatchSummary, InputFile,
+    Outcome, Verdict,
+};
+use true_iso::cli::{AnalyzeArgs, AtlasArgs, Command, CorrectArgs, InputArgs};
+use true_iso::detection::SpriteBounds;
 use true_iso::{
-    apply_affine_transform, compute_correction_matrix, crop_to_content, detect_isometric_angles,
-    resize_to_fit, Cli,
+    load_image, pack_atlas, pack_grid, slice_grid, split_sprites, write_anchor_file, write_report,
+    AnchorRecord, AtlasSprite, Cli, CorrectionOptions, CorrectionPipeline, CorrectionReport,
+    FileRecord, GridLayout, ProjectConfig, TrueIsoError,
 };
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+fn main() -> ExitCode {
+    let mut cli = Cli::parse();
+
+    // Config errors are reported once logging is set up
+    let config = ProjectConfig::discover(cli.config.as_deref());
+    if let Ok(config) = &config {
+        cli.apply_config(config);
+    }
+
+    tracing_subscriber::fmt()
+        .with_max_level(cli.log_level())
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_target(false)
+        .without_time()
+        .init();
 
-    // Load input image
-    let img = ImageReader::open(&cli.input)
-        .with_context(|| format!("Failed to open input file: {:?}", cli.input))?
-        .decode()
-        .with_context(|| format!("Failed to decode image: {:?}", cli.input))?;
+    let succeeded = config.map_err(Into::into).and_then(|config| {
+        configure_jobs(&cli)?;
+        match cli.resolved_command() {
+            Command::Correct(args) => {
+                run_correct(&args, &config).map(|summary| !summary.has_failures())
+            }
+            Command::Analyze(args) => run_analyze(&args, &config).map(|summary| summary.is_clean()),
+            Command::Atlas(args) => {
+                run_atlas(&args, &config).map(|summary| !summary.has_failures())
+            }
+        }
+    });
 
-    if cli.verbose {
-        let (width, height) = (img.width(), img.height());
-        eprintln!("Loaded image: {:?} ({}x{})", cli.input, width, height);
-        eprintln!("Target ratio: {}:{}", cli.ratio.horizontal, cli.ratio.vertical);
-        eprintln!("Target angle: {:.3}°", cli.ratio.target_angle_degrees());
-        eprintln!();
+    match succeeded {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            error!("{:#}", err);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    // Detect isometric angles
-    let geometry = detect_isometric_angles(&img, cli.verbose)
-        .context("Failed to detect isometric geometry")?;
+/// Size the global worker pool if `--jobs` was given
+fn configure_jobs(cli: &Cli) -> Result<()> {
+    if let Some(jobs) = cli.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .context("Failed to configure worker threads")?;
+    }
+    Ok(())
+}
 
-    if cli.verbose {
-        eprintln!();
+/// Resolve the inputs of a command, failing if nothing matched
+fn resolve_inputs(args: &InputArgs, exclude: &[PathBuf]) -> Result<Vec<InputFile>> {
+    let inputs = collect_inputs(&args.inputs, args.recursive, exclude)?;
+    if inputs.is_empty() {
+        bail!("No input images found");
     }
+    Ok(inputs)
+}
 
-    // Check if correction is needed
-    let tolerance = 2.0; // degrees
-    if geometry.angles.is_close_to_target(&cli.ratio, tolerance) {
-        eprintln!(
-            "Image already has correct isometric proportions (within {:.1}° tolerance)",
-            tolerance
+/// Build one pipeline per input, rejecting invalid detector parameters before any file is read
+fn pipelines_for<F>(inputs: &[InputFile], options_for: F) -> Result<Vec<CorrectionPipeline>>
+where
+    F: Fn(&Path) -> CorrectionOptions,
+{
+    inputs
+        .iter()
+        .map(|input| {
+            let options = options_for(&input.path);
+            options
+                .detection
+                .validate()
+                .with_context(|| format!("Invalid options for {:?}", input.path))?;
+            Ok(CorrectionPipeline::with_options(options))
+        })
+        .collect()
+}
+
+/// Resolve all inputs and correct them on the worker pool
+fn run_correct(args: &CorrectArgs, config: &ProjectConfig) -> Result<BatchSummary> {
+    let inputs = resolve_inputs(&args.input, &[])?;
+    let pipelines = pipelines_for(&inputs, |input| {
+        args.correction.correction_options(input, config)
+    })?;
+
+    if args.output.is_some() && inputs.len() > 1 {
+        bail!(
+            "--output can only be used with a single input ({} found); use --output-dir instead",
+            inputs.len()
         );
-        if cli.verbose {
-            eprintln!(
-                "Detected: left={:.2}°, right={:.2}°",
-                geometry.angles.left_angle, geometry.angles.right_angle
-            );
-            eprintln!("Target: ±{:.3}°", cli.ratio.target_angle_degrees());
-        }
-        // Still crop and resize even if angles are correct
-        let output_path = cli.output_path();
-        let rgba = img.to_rgba8();
-        let cropped = crop_to_content(&rgba);
-        let final_image = resize_to_fit(&cropped, cli.size);
-
-        final_image
-            .save(&output_path)
-            .with_context(|| format!("Failed to save output: {:?}", output_path))?;
-        eprintln!(
-            "Saved (angles unchanged, cropped & resized): {:?}",
-            output_path
+    }
+    // Inputs from different roots can share a relative path (or a stem, next to each other)
+    let outputs = inputs.iter().map(|input| (args.output_path(input), input));
+    if let Some((output, first, second)) = find_collision(outputs) {
+        bail!(
+            "{:?} and {:?} would both be written to {:?}; correct them separately",
+            first.path,
+            second.path,
+            output
         );
-        eprintln!(
-            "Dimensions: {}x{} -> {}x{}",
-            img.width(),
-            img.height(),
-            final_image.width(),
-            final_image.height()
+    }
+
+    // Files are processed in parallel; each one produces the same output as a serial run
+    let results: Vec<(Result<Outcome>, FileRecord)> = inputs
+        .par_iter()
+        .zip(&pipelines)
+        .flat_map_iter(|(input, pipeline)| {
+            let span = debug_span!("file", path = %input.path.display());
+            let _guard = span.enter();
+
+            if let Some(layout) = args.grid.layout() {
+                process_grid(pipeline, input, &layout, args)
+            } else if args.split {
+                process_sheet(pipeline, input, args)
+            } else {
+                let output_path = args.output_path(input);
+                let result = load_image(&input.path)
+                    .map_err(Into::into)
+                    .and_then(|img| process_image(pipeline, &img, input, &output_path, args));
+                vec![record_result(input, None, &output_path, result)]
+            }
+        })
+        .collect();
+
+    let mut summary = BatchSummary::default();
+    for (result, _) in &results {
+        summary.record(result);
+    }
+
+    if let Some(path) = &args.report {
+        let records: Vec<FileRecord> = results.into_iter().map(|(_, record)| record).collect();
+        write_report(path, &records)?;
+    }
+
+    log_summary(&summary);
+    Ok(summary)
+}
+
+/// Correct all inputs on the worker pool and pack the results into one atlas
+///
+/// Files that fail are left out of the atlas.
+fn run_atlas(args: &AtlasArgs, config: &ProjectConfig) -> Result<BatchSummary> {
+    // Never pack the atlas written by a previous run into the new one
+    let inputs = resolve_inputs(&args.input, &[args.output.clone(), args.metadata_path()])?;
+    let pipelines = pipelines_for(&inputs, |input| {
+        args.correction.correction_options(input, config)
+    })?;
+    // Frames are keyed by relative path, which inputs from different roots can share
+    let names = inputs.iter().map(|input| (frame_name(input), input));
+    if let Some((name, first, second)) = find_collision(names) {
+        bail!(
+            "{:?} and {:?} would both be packed as frame '{}'; pack them separately",
+            first.path,
+            second.path,
+            name
         );
-        return Ok(());
     }
 
-    // Report detected angles
-    eprintln!(
-        "Detected angles: left={:.2}°, right={:.2}°",
-        geometry.angles.left_angle, geometry.angles.right_angle
-    );
-    eprintln!(
-        "Target angles: left=-{:.3}°, right=+{:.3}°",
-        cli.ratio.target_angle_degrees(),
-        cli.ratio.target_angle_degrees()
-    );
+    let results: Vec<Result<CorrectionReport>> = inputs
+        .par_iter()
+        .zip(&pipelines)
+        .map(|(input, pipeline)| {
+            let span = debug_span!("file", path = %input.path.display());
+            let _guard = span.enter();
+
+            let result = load_image(&input.path)
+                .and_then(|img| pipeline.run(&img))
+                .map_err(Into::into);
+            match &result {
+                Ok(report) => log_correction(report, input, &args.output),
+                Err(err) => error!("Failed: {:?}: {:#}", input.path, err),
+            }
+            result
+        })
+        .collect();
+
+    let mut summary = BatchSummary::default();
+    let mut sprites = Vec::new();
+    for (input, result) in inputs.iter().zip(results) {
+        let outcome = result.map(|report| {
+            let outcome = if report.skipped {
+                Outcome::Skipped
+            } else {
+                Outcome::Corrected
+            };
+            sprites.push(AtlasSprite {
+                name: frame_name(input),
+                image: report.image,
+                anchor: report.anchor.map(|anchor| anchor.pixel),
+            });
+            outcome
+        });
+        summary.record(&outcome);
+    }
+    log_summary(&summary);
+    if sprites.is_empty() {
+        bail!("No image could be corrected, not writing an atlas");
+    }
+
+    let atlas = pack_atlas(sprites, args.max_size(), args.padding())?;
+    save_image(&atlas.image, &args.output)?;
+    let image_name = args
+        .output
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let metadata_path = args.metadata_path();
+    atlas.write_metadata(&metadata_path, &image_name)?;
 
-    // Compute correction matrix
-    let correction_matrix = compute_correction_matrix(
-        &geometry.angles,
-        &cli.ratio,
-        geometry.center,
+    let (width, height) = atlas.image.dimensions();
+    info!(
+        "Packed {} sprites into {:?} ({}x{}), metadata: {:?}",
+        atlas.frames.len(),
+        args.output,
+        width,
+        height,
+        metadata_path
     );
 
-    if cli.verbose {
-        eprintln!();
-        eprintln!("Correction matrix:");
-        for row in 0..3 {
-            eprintln!(
-                "  [{:8.4}, {:8.4}, {:8.4}]",
-                correction_matrix[(row, 0)],
-                correction_matrix[(row, 1)],
-                correction_matrix[(row, 2)]
-            );
+    Ok(summary)
+}
+
+/// Frame name of an input in the atlas metadata: its relative path with `/` separators
+fn frame_name(input: &InputFile) -> String {
+    let parts: Vec<_> = input
+        .relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    parts.join("/")
+}
+
+/// Log the tally of a batch with several files or any failure
+fn log_summary(summary: &BatchSummary) {
+    if summary.total() > 1 || summary.has_failures() {
+        info!(
+            "Processed {} files: {} corrected, {} skipped, {} failed",
+            summary.total(),
+            summary.corrected,
+            summary.skipped,
+            summary.failed
+        );
+    }
+}
+
+/// Correct each sprite of a sheet on its own, writing `<stem>_corrected_<n>.png`
+fn process_sheet(
+    pipeline: &CorrectionPipeline,
+    input: &InputFile,
+    args: &CorrectArgs,
+) -> Vec<(Result<Outcome>, FileRecord)> {
+    let alpha_threshold = pipeline.options().detection.alpha_threshold;
+    let sprites = match load_image(&input.path) {
+        Ok(img) => split_sprites(&img.to_rgba8(), alpha_threshold),
+        Err(err) => {
+            let output_path = args.output_path(input);
+            return vec![record_result(input, None, &output_path, Err(err.into()))];
         }
-        eprintln!();
+    };
+    if sprites.is_empty() {
+        let err = TrueIsoError::EmptySprite { alpha_threshold };
+        let output_path = args.output_path(input);
+        return vec![record_result(input, None, &output_path, Err(err.into()))];
     }
 
-    // Apply transformation
-    let rgba = img.to_rgba8();
-    let transformed = apply_affine_transform(&rgba, &correction_matrix, cli.verbose);
+    sprites
+        .into_iter()
+        .enumerate()
+        .map(|(i, sprite)| {
+            let index = i + 1;
+            let span = debug_span!("sprite", index);
+            let _guard = span.enter();
 
-    // Crop to content (remove padding)
-    let cropped = crop_to_content(&transformed);
+            let output_path = args.sprite_output_path(input, index);
+            let img = DynamicImage::ImageRgba8(sprite.image);
+            let result = process_image(pipeline, &img, input, &output_path, args);
+            record_result(input, Some((index, sprite.bounds)), &output_path, result)
+        })
+        .collect()
+}
 
-    if cli.verbose {
-        eprintln!(
-            "Cropped: {}x{} -> {}x{}",
-            transformed.width(),
-            transformed.height(),
-            cropped.width(),
-            cropped.height()
-        );
+/// Correct each cell of a grid-based sheet on its own and repack them into `<stem>_corrected.png`
+///
+/// Empty cells and cells that fail stay empty in the repacked sheet.
+fn process_grid(
+    pipeline: &CorrectionPipeline,
+    input: &InputFile,
+    layout: &GridLayout,
+    args: &CorrectArgs,
+) -> Vec<(Result<Outcome>, FileRecord)> {
+    let output_path = args.output_path(input);
+    let alpha_threshold = pipeline.options().detection.alpha_threshold;
+    let sliced = load_image(&input.path).and_then(|img| {
+        let img = img.to_rgba8();
+        let (columns, ..) = layout.resolve(img.width(), img.height())?;
+        let cells = slice_grid(&img, layout, alpha_threshold)?;
+        if cells.iter().all(Option::is_none) {
+            return Err(TrueIsoError::EmptySprite { alpha_threshold });
+        }
+        Ok((columns, cells))
+    });
+    let (columns, cells) = match sliced {
+        Ok(sliced) => sliced,
+        Err(err) => return vec![record_result(input, None, &output_path, Err(err.into()))],
+    };
+
+    let results: Vec<Option<(usize, SpriteBounds, Result<CorrectionReport>)>> = cells
+        .into_iter()
+        .enumerate()
+        .map(|(i, cell)| {
+            let cell = cell?;
+            let index = i + 1;
+            let span = debug_span!("cell", index);
+            let _guard = span.enter();
+
+            let result = pipeline
+                .run(&DynamicImage::ImageRgba8(cell.image))
+                .map_err(Into::into)
+                .inspect(|report| log_correction(report, input, &output_path));
+            Some((index, cell.bounds, result))
+        })
+        .collect();
+
+    let images: Vec<Option<RgbaImage>> = results
+        .iter()
+        .map(|result| match result {
+            Some((_, _, Ok(report))) => Some(report.image.clone()),
+            _ => None,
+        })
+        .collect();
+    if let Err(err) = save_image(&pack_grid(&images, columns), &output_path) {
+        return vec![record_result(input, None, &output_path, Err(err))];
+    }
+
+    results
+        .into_iter()
+        .flatten()
+        .map(|(index, bounds, result)| {
+            record_result(input, Some((index, bounds)), &output_path, result)
+        })
+        .collect()
+}
+
+/// Turn the result of one correction into its outcome and report record, logging failures
+fn record_result(
+    input: &InputFile,
+    sprite: Option<(usize, SpriteBounds)>,
+    output_path: &Path,
+    result: Result<CorrectionReport>,
+) -> (Result<Outcome>, FileRecord) {
+    let (outcome, record) = match result {
+        Ok(report) => {
+            let record = FileRecord::corrected(&input.path, output_path, &report);
+            let outcome = if report.skipped {
+                Outcome::Skipped
+            } else {
+                Outcome::Corrected
+            };
+            (Ok(outcome), record)
+        }
+        Err(err) => {
+            match sprite {
+                Some((index, _)) => {
+                    error!("Failed: {:?} (sprite {}): {:#}", input.path, index, err)
+                }
+                None => error!("Failed: {:?}: {:#}", input.path, err),
+            }
+            let record = FileRecord::failed(&input.path, format!("{:#}", err));
+            (Err(err), record)
+        }
+    };
+    match sprite {
+        Some((index, bounds)) => (outcome, record.with_sprite(index, bounds)),
+        None => (outcome, record),
+    }
+}
+
+/// Correct a single image, writing the result to `output_path` (and its anchor next to it)
+fn process_image(
+    pipeline: &CorrectionPipeline,
+    img: &DynamicImage,
+    input: &InputFile,
+    output_path: &Path,
+    args: &CorrectArgs,
+) -> Result<CorrectionReport> {
+    let report = pipeline.run(img)?;
+
+    save_image(&report.image, output_path)?;
+    if args.anchor_file {
+        let record = AnchorRecord::new(output_path, &report);
+        write_anchor_file(&anchor_file_path(output_path), &record)?;
     }
+    log_correction(&report, input, output_path);
 
-    // Resize to target size
-    let final_image = resize_to_fit(&cropped, cli.size);
+    Ok(report)
+}
 
-    if cli.verbose {
-        eprintln!(
-            "Resized: {}x{} -> {}x{} (target: {})",
-            cropped.width(),
-            cropped.height(),
-            final_image.width(),
-            final_image.height(),
-            cli.size
+/// Log what happened to one image (or cell) and where it was written
+fn log_correction(report: &CorrectionReport, input: &InputFile, output_path: &Path) {
+    let (in_w, in_h) = report.input_size;
+    let (out_w, out_h) = report.image.dimensions();
+    if let Some(low) = &report.low_confidence {
+        warn!(
+            "Low confidence on the {} side (left={:.2}, right={:.2}, policy: {}): {:?}",
+            low.side,
+            report.geometry.angles.left_confidence,
+            report.geometry.angles.right_confidence,
+            low.policy,
+            input.path
+        );
+    }
+    if let Some(reason) = report.height_skipped {
+        warn!(
+            "Height policy not applied ({}), walls left as they are: {:?}",
+            reason, input.path
         );
     }
 
-    // Save result
-    let output_path = cli.output_path();
-    final_image
-        .save(&output_path)
-        .with_context(|| format!("Failed to save output: {:?}", output_path))?;
-
-    eprintln!();
-    eprintln!("Saved corrected image: {:?}", output_path);
-    eprintln!(
-        "Dimensions: {}x{} -> {}x{}",
-        img.width(),
-        img.height(),
-        final_image.width(),
-        final_image.height()
+    if report.skipped {
+        info!(
+            "Skipped (angles unchanged, cropped & resized): {:?} -> {:?} ({}x{} -> {}x{})",
+            input.path, output_path, in_w, in_h, out_w, out_h
+        );
+    } else {
+        // The perspective correction does not rotate the sprite as a whole
+        let rotation = report
+            .decomposition
+            .map(|decomposition| format!(", rotation={:.2}°", decomposition.rotation))
+            .unwrap_or_default();
+        info!(
+            "Corrected (left={:.2}°, right={:.2}°{}): {:?} -> {:?} ({}x{} -> {}x{})",
+            report.geometry.angles.left_angle,
+            report.geometry.angles.right_angle,
+            rotation,
+            input.path,
+            output_path,
+            in_w,
+            in_h,
+            out_w,
+            out_h
+        );
+    }
+}
+
+/// Resolve all inputs and check their angles against the target on the worker pool
+fn run_analyze(args: &AnalyzeArgs, config: &ProjectConfig) -> Result<AnalysisSummary> {
+    let inputs = resolve_inputs(&args.input, &[])?;
+    let pipelines = pipelines_for(&inputs, |input| args.correction_options(input, config))?;
+
+    let results: Vec<Result<Verdict>> = inputs
+        .par_iter()
+        .zip(&pipelines)
+        .map(|(input, pipeline)| {
+            let span = debug_span!("file", path = %input.path.display());
+            let _guard = span.enter();
+
+            let result = analyze_file(pipeline, input);
+            if let Err(err) = &result {
+                error!("Failed: {:?}: {:#}", input.path, err);
+            }
+            result
+        })
+        .collect();
+
+    let mut summary = AnalysisSummary::default();
+    for result in &results {
+        summary.record(result);
+    }
+
+    let tally = format!(
+        "Analyzed {} files: {} within tolerance, {} out of tolerance, {} low confidence, \
+         {} failed",
+        summary.total(),
+        summary.within_tolerance,
+        summary.out_of_tolerance,
+        summary.low_confidence,
+        summary.failed
     );
+    if summary.is_clean() {
+        info!("{}", tally);
+    } else {
+        warn!("{}", tally);
+    }
 
-    Ok(())
+    Ok(summary)
+}
+
+/// Analyze a single image, judging it the way `correct` would
+fn analyze_file(pipeline: &CorrectionPipeline, input: &InputFile) -> Result<Verdict> {
+    let img = load_image(&input.path)?;
+    let analysis = pipeline.analyze(&img)?;
+    let angles = &analysis.geometry.angles;
+
+    if let Some(low) = &analysis.low_confidence {
+        warn!(
+            "Low confidence on the {} side (left={:.2}, right={:.2}, policy: {}): {:?}",
+            low.side, angles.left_confidence, angles.right_confidence, low.policy, input.path
+        );
+    }
+
+    if analysis.is_refused() {
+        return Ok(Verdict::LowConfidence);
+    }
+    if analysis.within_tolerance {
+        info!(
+            "OK (left={:.2}° Δ{:.2}°, right={:.2}° Δ{:.2}°, rotation={:.2}°): {:?}",
+            angles.left_angle,
+            analysis.left_deviation,
+            angles.right_angle,
+            analysis.right_deviation,
+            analysis.decomposition.rotation,
+            input.path
+        );
+    } else {
+        warn!(
+            "Out of tolerance (left={:.2}° Δ{:.2}°, right={:.2}° Δ{:.2}°, rotation={:.2}°, \
+             tolerance {:.2}°): {:?}",
+            angles.left_angle,
+            analysis.left_deviation,
+            angles.right_angle,
+            analysis.right_deviation,
+            analysis.decomposition.rotation,
+            pipeline.options().tolerance,
+            input.path
+        );
+    }
+
+    Ok(if analysis.within_tolerance {
+        Verdict::WithinTolerance
+    } else {
+        Verdict::OutOfTolerance
+    })
+}
+
+/// Save an image, creating parent directories as needed
+fn save_image(image: &RgbaImage, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create output directory: {:?}", parent))?;
+    }
+    image
+        .save(path)
+        .with_context(|| format!("Failed to save output: {:?}", path))
 }
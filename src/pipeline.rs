@@ -0,0 +1,1184 @@
+use clap::ValueEnum;
+use image::{DynamicImage, ImageReader, RgbaImage};
+use nalgebra::Matrix3;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use tracing::debug;
+
+use crate::detection::{
+    detect_isometric_angles, find_sprite_bounds, fit_diamond, fit_perspective_diamond,
+    locate_sprite, DetectedGeometry, DetectionConfig,
+};
+use crate::error::{Result, TrueIsoError};
+use crate::geometry::{
+    compute_correction_matrix, compute_output_bounds, compute_perspective_matrix, transform_point,
+    AngleDecomposition, AngleOverride, DetectedAngles, DetectedDiamond, HeightPolicy,
+    IsometricRatio, Side,
+};
+use crate::pixel_art::{enforce_stair_steps, integer_scale, snap_to_palette, Palette, Scale2x};
+use crate::transform::{
+    apply_projective_transform, crop_to_content, place_on_canvas, resize_by, Interpolation,
+};
+
+/// Walls shorter than this (in pixels) are treated as a flat tile by the height policy
+const MIN_WALL_HEIGHT: f64 = 2.0;
+
+/// Kind of transform used to correct the sprite
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum CorrectionMode {
+    /// Affine warp fixing the slopes (parallel edges stay parallel)
+    #[default]
+    Affine,
+    /// Homography from the four top-face corners, also removing foreshortening
+    ///
+    /// Falls back to `affine` when the corners cannot be located.
+    Perspective,
+}
+
+impl fmt::Display for CorrectionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CorrectionMode::Affine => write!(f, "affine"),
+            CorrectionMode::Perspective => write!(f, "perspective"),
+        }
+    }
+}
+
+/// What to do when a side's detection confidence is below the minimum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LowConfidencePolicy {
+    /// Leave the angles alone (only crop and resize)
+    #[default]
+    Skip,
+    /// Correct anyway, assuming the target angle for the low-confidence side
+    Warn,
+    /// Fail with [`TrueIsoError::LowConfidence`]
+    Fail,
+    /// Reuse the confident side's magnitude for the low-confidence side
+    ///
+    /// Falls back to `skip` when neither side is confident.
+    Mirror,
+}
+
+impl fmt::Display for LowConfidencePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LowConfidencePolicy::Skip => write!(f, "skip"),
+            LowConfidencePolicy::Warn => write!(f, "warn"),
+            LowConfidencePolicy::Fail => write!(f, "fail"),
+            LowConfidencePolicy::Mirror => write!(f, "mirror"),
+        }
+    }
+}
+
+/// Low-confidence handling that was applied to an image
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LowConfidence {
+    /// Side(s) below the minimum confidence
+    pub side: Side,
+    /// Policy that was applied
+    pub policy: LowConfidencePolicy,
+}
+
+/// Why a `--height` policy other than `preserve` left the walls as they were
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HeightSkipped {
+    /// The top face's corners could not be located, so the target height is unknown
+    NoTopFace,
+    /// No vertical edges agree on the walls' direction
+    NoVerticalEdges,
+    /// Nothing extends below the top face
+    NoWalls,
+    /// The perspective correction maps the top face only
+    Perspective,
+}
+
+impl fmt::Display for HeightSkipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightSkipped::NoTopFace => write!(f, "top face not found"),
+            HeightSkipped::NoVerticalEdges => write!(f, "no vertical edges detected"),
+            HeightSkipped::NoWalls => write!(f, "no walls below the top face"),
+            HeightSkipped::Perspective => write!(f, "not supported in perspective mode"),
+        }
+    }
+}
+
+/// Point of the top diamond reported as the tile anchor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnchorPoint {
+    /// Bottom vertex, where the tile meets the ground in front
+    #[default]
+    BottomVertex,
+    /// Center of the diamond
+    FootprintCenter,
+}
+
+impl fmt::Display for AnchorPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnchorPoint::BottomVertex => write!(f, "bottom-vertex"),
+            AnchorPoint::FootprintCenter => write!(f, "footprint-center"),
+        }
+    }
+}
+
+/// Tile anchor in the output image
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Anchor {
+    /// Which point of the top diamond this is
+    pub point: AnchorPoint,
+    /// Position in output pixels
+    pub pixel: (f64, f64),
+    /// Position as a fraction of the output width and height
+    pub normalized: (f64, f64),
+}
+
+/// Options controlling a correction run
+#[derive(Debug, Clone, Copy)]
+pub struct CorrectionOptions {
+    /// Target isometric ratio
+    pub ratio: IsometricRatio,
+    /// Skip the geometric correction if both angles are within this many degrees of the target
+    pub tolerance: f64,
+    /// Output size (longest side in pixels)
+    pub size: u32,
+    /// Grid tile width; if set, the footprint is scaled to one tile instead of to `size`
+    pub tile_width: Option<u32>,
+    /// Round the footprint to a whole number of tiles instead of scaling it to exactly one
+    pub multi_tile: bool,
+    /// Fixed output canvas; the footprint is scaled to its width (or to one tile of
+    /// `tile_width`)
+    pub canvas: Option<(u32, u32)>,
+    /// Canvas pixel the tile anchor is placed at (by default the footprint rests on the
+    /// bottom edge, horizontally centered)
+    pub canvas_anchor: Option<(u32, u32)>,
+    /// Resampling method for warping and resizing
+    pub interpolation: Interpolation,
+    /// Keep the input palette and hard pixel edges (overrides `interpolation`)
+    pub pixel_art: bool,
+    /// Affine or perspective correction
+    pub mode: CorrectionMode,
+    /// Wall height of blocks after an affine correction
+    pub height: HeightPolicy,
+    /// Point of the top diamond reported as the tile anchor
+    pub anchor: AnchorPoint,
+    /// Detection parameters (including the alpha threshold)
+    pub detection: DetectionConfig,
+    /// Manual angles used instead of the detected ones
+    pub angles: AngleOverride,
+    /// Sides with a lower confidence than this are handled by `on_low_confidence`
+    pub min_confidence: f64,
+    /// What to do with low-confidence sides
+    pub on_low_confidence: LowConfidencePolicy,
+}
+
+impl Default for CorrectionOptions {
+    fn default() -> Self {
+        Self {
+            ratio: IsometricRatio::default(),
+            tolerance: 2.0,
+            size: 256,
+            tile_width: None,
+            multi_tile: false,
+            canvas: None,
+            canvas_anchor: None,
+            interpolation: Interpolation::default(),
+            pixel_art: false,
+            mode: CorrectionMode::default(),
+            height: HeightPolicy::default(),
+            anchor: AnchorPoint::default(),
+            detection: DetectionConfig::default(),
+            angles: AngleOverride::default(),
+            min_confidence: 0.1,
+            on_low_confidence: LowConfidencePolicy::default(),
+        }
+    }
+}
+
+/// Open and decode an image file
+pub fn load_image(path: &Path) -> Result<DynamicImage> {
+    let io_error = |source| TrueIsoError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    ImageReader::open(path)
+        .map_err(io_error)?
+        .with_guessed_format()
+        .map_err(io_error)?
+        .decode()
+        .map_err(|source| TrueIsoError::Image {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+/// Everything the pipeline found and produced for one image
+#[derive(Debug, Clone)]
+pub struct CorrectionReport {
+    /// Detected angles, sprite bounds and line count
+    pub geometry: DetectedGeometry,
+    /// Target angle in degrees
+    pub target_angle: f64,
+    /// True if the angles were already within tolerance and no warp was applied
+    pub skipped: bool,
+    /// Forward correction matrix (identity when skipped, projective in perspective mode)
+    pub matrix: Matrix3<f64>,
+    /// Rotation and level slope the affine correction removed (`None` when skipped or in
+    /// perspective mode)
+    pub decomposition: Option<AngleDecomposition>,
+    /// Mode that was applied (`affine` if perspective corners could not be located)
+    pub mode: CorrectionMode,
+    /// Manual angles that replaced detection
+    pub manual_angles: AngleOverride,
+    /// Low-confidence policy that fired, if any
+    pub low_confidence: Option<LowConfidence>,
+    /// Why the height policy was not applied to a corrected sprite, if it was not
+    pub height_skipped: Option<HeightSkipped>,
+    /// Input dimensions
+    pub input_size: (u32, u32),
+    /// Dimensions after the warp (equal to the input when skipped)
+    pub transformed_size: (u32, u32),
+    /// Dimensions after cropping to content
+    pub cropped_size: (u32, u32),
+    /// Tile anchor in the output image, if a top diamond was found
+    pub anchor: Option<Anchor>,
+    /// Final corrected, cropped and resized image
+    pub image: RgbaImage,
+}
+
+/// Result of analyzing an image without correcting it
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    /// Detected angles, sprite bounds and line count
+    pub geometry: DetectedGeometry,
+    /// Target angle in degrees
+    pub target_angle: f64,
+    /// Detected angles split into a rotation and a level slope
+    pub decomposition: AngleDecomposition,
+    /// Deviation of the left angle from the target in degrees
+    pub left_deviation: f64,
+    /// Deviation of the right angle from the target in degrees
+    pub right_deviation: f64,
+    /// True if both deviations are within the tolerance
+    pub within_tolerance: bool,
+    /// Set if a side's confidence was below the minimum (angles and deviations are the
+    /// ones `run` would correct with)
+    pub low_confidence: Option<LowConfidence>,
+}
+
+impl Analysis {
+    /// True if `run` would leave the angles alone because of low confidence
+    pub fn is_refused(&self) -> bool {
+        matches!(
+            self.low_confidence,
+            Some(LowConfidence {
+                policy: LowConfidencePolicy::Skip,
+                ..
+            })
+        )
+    }
+
+    /// Largest deviation of either side in degrees
+    pub fn max_deviation(&self) -> f64 {
+        self.left_deviation.max(self.right_deviation)
+    }
+}
+
+/// Detect, correct, crop and resize an isometric sprite
+///
+/// ```no_run
+/// use true_iso::{CorrectionPipeline, IsometricRatio};
+///
+/// let img = image::open("tile.png").unwrap();
+/// let report = CorrectionPipeline::new()
+///     .ratio(IsometricRatio::new(2.0, 1.0))
+///     .size(128)
+///     .run(&img)
+///     .unwrap();
+/// report.image.save("tile_corrected.png").unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CorrectionPipeline {
+    options: CorrectionOptions,
+}
+
+impl CorrectionPipeline {
+    /// Create a pipeline with default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a pipeline from a full set of options
+    pub fn with_options(options: CorrectionOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn options(&self) -> &CorrectionOptions {
+        &self.options
+    }
+
+    pub fn ratio(mut self, ratio: IsometricRatio) -> Self {
+        self.options.ratio = ratio;
+        self
+    }
+
+    pub fn tolerance(mut self, degrees: f64) -> Self {
+        self.options.tolerance = degrees;
+        self
+    }
+
+    pub fn size(mut self, size: u32) -> Self {
+        self.options.size = size;
+        self
+    }
+
+    pub fn tile_width(mut self, tile_width: u32) -> Self {
+        self.options.tile_width = Some(tile_width);
+        self
+    }
+
+    pub fn multi_tile(mut self, multi_tile: bool) -> Self {
+        self.options.multi_tile = multi_tile;
+        self
+    }
+
+    pub fn canvas(mut self, width: u32, height: u32) -> Self {
+        self.options.canvas = Some((width, height));
+        self
+    }
+
+    pub fn canvas_anchor(mut self, x: u32, y: u32) -> Self {
+        self.options.canvas_anchor = Some((x, y));
+        self
+    }
+
+    pub fn alpha_threshold(mut self, threshold: u8) -> Self {
+        self.options.detection.alpha_threshold = threshold;
+        self
+    }
+
+    pub fn interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.options.interpolation = interpolation;
+        self
+    }
+
+    pub fn pixel_art(mut self, pixel_art: bool) -> Self {
+        self.options.pixel_art = pixel_art;
+        self
+    }
+
+    pub fn mode(mut self, mode: CorrectionMode) -> Self {
+        self.options.mode = mode;
+        self
+    }
+
+    pub fn height(mut self, height: HeightPolicy) -> Self {
+        self.options.height = height;
+        self
+    }
+
+    pub fn anchor(mut self, anchor: AnchorPoint) -> Self {
+        self.options.anchor = anchor;
+        self
+    }
+
+    pub fn detection(mut self, detection: DetectionConfig) -> Self {
+        self.options.detection = detection;
+        self
+    }
+
+    pub fn angles(mut self, angles: AngleOverride) -> Self {
+        self.options.angles = angles;
+        self
+    }
+
+    pub fn min_confidence(mut self, min_confidence: f64) -> Self {
+        self.options.min_confidence = min_confidence;
+        self
+    }
+
+    pub fn on_low_confidence(mut self, policy: LowConfidencePolicy) -> Self {
+        self.options.on_low_confidence = policy;
+        self
+    }
+
+    /// Canvas pixel the tile anchor goes to, for a footprint `footprint` pixels wide
+    ///
+    /// Defaults to where the anchor lies when the footprint rests on the bottom edge,
+    /// horizontally centered.
+    fn canvas_point(&self, width: u32, height: u32, footprint: f64) -> (f64, f64) {
+        let options = &self.options;
+        if let Some((x, y)) = options.canvas_anchor {
+            return (x as f64, y as f64);
+        }
+        let bottom = height as f64;
+        let y = match options.anchor {
+            AnchorPoint::BottomVertex => bottom,
+            AnchorPoint::FootprintCenter => {
+                bottom - footprint * options.ratio.vertical / options.ratio.horizontal / 2.0
+            }
+        };
+        (width as f64 / 2.0, y)
+    }
+
+    /// Apply the low-confidence policy to the detected angles
+    ///
+    /// A side without any lines always counts as low confidence. Returns the policy
+    /// that fired, or `None` if both sides are confident enough.
+    fn check_confidence(&self, angles: &mut DetectedAngles) -> Result<Option<LowConfidence>> {
+        let options = &self.options;
+        let is_low = |confidence: f64| confidence <= 0.0 || confidence < options.min_confidence;
+
+        let side = match (
+            is_low(angles.left_confidence),
+            is_low(angles.right_confidence),
+        ) {
+            (false, false) => return Ok(None),
+            (true, false) => Side::Left,
+            (false, true) => Side::Right,
+            (true, true) => Side::Both,
+        };
+
+        let policy = match options.on_low_confidence {
+            LowConfidencePolicy::Mirror if side == Side::Both => LowConfidencePolicy::Skip,
+            policy => policy,
+        };
+
+        let target_angle = options.ratio.target_angle_degrees();
+        match policy {
+            LowConfidencePolicy::Fail => {
+                return Err(TrueIsoError::LowConfidence {
+                    side,
+                    confidence: angles.left_confidence.min(angles.right_confidence),
+                    min_confidence: options.min_confidence,
+                });
+            }
+            LowConfidencePolicy::Skip => {
+                debug!(%side, "low detection confidence, leaving angles unchanged");
+            }
+            LowConfidencePolicy::Warn => {
+                if side != Side::Right {
+                    angles.left_angle = -target_angle;
+                }
+                if side != Side::Left {
+                    angles.right_angle = target_angle;
+                }
+                debug!(%side, target_angle, "low detection confidence, assuming the target angle");
+            }
+            LowConfidencePolicy::Mirror => {
+                if side == Side::Left {
+                    angles.left_angle = -angles.right_angle.abs();
+                } else {
+                    angles.right_angle = angles.left_angle.abs();
+                }
+                debug!(%side, "low detection confidence, mirroring the other side");
+            }
+        }
+
+        Ok(Some(LowConfidence { side, policy }))
+    }
+
+    /// Length the upright unit vector should have after the affine correction
+    ///
+    /// The walls' current height is measured from the top face's bottom vertex down to
+    /// the sprite's lowest pixel. If the policy cannot be applied, the reason is returned
+    /// and the height is preserved.
+    fn vertical_scale(
+        &self,
+        geometry: &DetectedGeometry,
+    ) -> std::result::Result<f64, HeightSkipped> {
+        let options = &self.options;
+        if options.height == HeightPolicy::Preserve {
+            return Ok(1.0);
+        }
+        let diamond = geometry.diamond.as_ref().ok_or(HeightSkipped::NoTopFace)?;
+        let Some(height) = options.height.wall_height(&options.ratio, diamond.width()) else {
+            return Ok(1.0);
+        };
+        let tilt = geometry
+            .angles
+            .vertical_tilt
+            .ok_or(HeightSkipped::NoVerticalEdges)?;
+
+        let (_, y0, _, sprite_height) = geometry.bounds;
+        let wall = ((y0 + sprite_height) as f64 - diamond.bottom.1) / tilt.to_radians().cos();
+        if wall < MIN_WALL_HEIGHT {
+            return Err(HeightSkipped::NoWalls);
+        }
+        debug!(wall, target = height, policy = %options.height, "scaling wall height");
+        Ok(height / wall)
+    }
+
+    /// Detect the sprite geometry, replacing manually specified angles
+    ///
+    /// If both angles are given, line detection is skipped entirely and only the
+    /// sprite bounds are measured.
+    fn detect(&self, img: &DynamicImage) -> Result<DetectedGeometry> {
+        let options = &self.options;
+        let manual = &options.angles;
+
+        let mut geometry = if manual.is_complete() {
+            let detection = &options.detection;
+            detection.validate()?;
+            let (bounds, center) = locate_sprite(&img.to_rgba8(), detection.alpha_threshold)?;
+            DetectedGeometry {
+                angles: DetectedAngles::new(0.0, 0.0, 0.0, 0.0),
+                bounds,
+                center,
+                line_count: 0,
+                diamond: None,
+            }
+        } else {
+            detect_isometric_angles(img, &options.detection)?
+        };
+
+        if !manual.is_empty() {
+            manual.apply(&mut geometry.angles);
+            debug!(left = ?manual.left, right = ?manual.right, "using manual angles");
+        }
+        Ok(geometry)
+    }
+
+    /// Detect the angles and compare them to the target without warping the image
+    ///
+    /// Manual angles are ignored: analysis always measures the image itself.
+    /// Low-confidence sides are handled by the same policy as in [`run`](Self::run).
+    pub fn analyze(&self, img: &DynamicImage) -> Result<Analysis> {
+        let options = &self.options;
+        let mut geometry = detect_isometric_angles(img, &options.detection)?;
+        // Same policy as `run`, so analysis flags exactly what a correction would do
+        let low_confidence = self.check_confidence(&mut geometry.angles)?;
+
+        let (left_deviation, right_deviation) = geometry.angles.deviation_from(&options.ratio);
+        let decomposition = geometry.angles.decompose();
+        let within_tolerance = geometry
+            .angles
+            .is_close_to_target(&options.ratio, options.tolerance);
+
+        debug!(
+            left_deviation,
+            right_deviation,
+            rotation = decomposition.rotation,
+            slope_error = decomposition.slope_error(&options.ratio),
+            tolerance = options.tolerance,
+            within_tolerance,
+            "analyzed angles"
+        );
+
+        Ok(Analysis {
+            geometry,
+            target_angle: options.ratio.target_angle_degrees(),
+            decomposition,
+            left_deviation,
+            right_deviation,
+            within_tolerance,
+            low_confidence,
+        })
+    }
+
+    /// Run the full pipeline on an image
+    pub fn run(&self, img: &DynamicImage) -> Result<CorrectionReport> {
+        let options = &self.options;
+        let rgba = img.to_rgba8();
+
+        debug!(
+            width = img.width(),
+            height = img.height(),
+            ratio = %format_args!("{}:{}", options.ratio.horizontal, options.ratio.vertical),
+            target_angle = options.ratio.target_angle_degrees(),
+            filter = %options.interpolation,
+            pixel_art = options.pixel_art,
+            "running correction pipeline"
+        );
+
+        let mut geometry = self.detect(img)?;
+        let low_confidence = self.check_confidence(&mut geometry.angles)?;
+        // Perspective mode measures every edge of the top face on its own; otherwise the
+        // corners follow the final angles, including manual and low-confidence replacements
+        let alpha_threshold = options.detection.alpha_threshold;
+        let perspective = match options.mode {
+            CorrectionMode::Perspective => {
+                let corners = fit_perspective_diamond(&rgba, alpha_threshold, geometry.bounds);
+                if corners.is_none() {
+                    debug!("top-face corners not found, falling back to affine correction");
+                }
+                corners
+            }
+            CorrectionMode::Affine => None,
+        };
+        let mode = match perspective {
+            Some(_) => CorrectionMode::Perspective,
+            None => CorrectionMode::Affine,
+        };
+        geometry.diamond = perspective
+            .or_else(|| fit_diamond(&rgba, alpha_threshold, geometry.bounds, &geometry.angles));
+        // Pixel art keeps the input colors: Scale2x warps, nearest-neighbour resizes, and the
+        // output is snapped back to the palette
+        let palette = options
+            .pixel_art
+            .then(|| Palette::extract(&rgba, alpha_threshold));
+        let filter = if options.pixel_art {
+            Interpolation::Nearest
+        } else {
+            options.interpolation
+        };
+        let refused = matches!(
+            low_confidence,
+            Some(LowConfidence {
+                policy: LowConfidencePolicy::Skip,
+                ..
+            })
+        );
+
+        let within_tolerance = match (mode, &geometry.diamond) {
+            (CorrectionMode::Perspective, Some(diamond)) => {
+                diamond.max_deviation_from(&options.ratio) < options.tolerance
+            }
+            _ => geometry
+                .angles
+                .is_close_to_target(&options.ratio, options.tolerance),
+        };
+        let skipped = refused || within_tolerance;
+
+        if skipped && !refused {
+            debug!(
+                tolerance = options.tolerance,
+                "angles already within tolerance, skipping warp"
+            );
+        }
+
+        // The height policy reshapes the walls of an affine correction
+        let vertical_scale = match mode {
+            _ if skipped || options.height == HeightPolicy::Preserve => Ok(1.0),
+            CorrectionMode::Perspective => Err(HeightSkipped::Perspective),
+            CorrectionMode::Affine => self.vertical_scale(&geometry),
+        };
+        let height_skipped = vertical_scale.err();
+        if let Some(reason) = height_skipped {
+            debug!(policy = %options.height, %reason, "height policy not applied");
+        }
+
+        let (matrix, decomposition, transformed) = if skipped {
+            // Still crop and resize even if angles are correct
+            (Matrix3::identity(), None, rgba)
+        } else {
+            let (matrix, decomposition) = match (mode, &geometry.diamond) {
+                (CorrectionMode::Perspective, Some(diamond)) => {
+                    (compute_perspective_matrix(diamond, &options.ratio)?, None)
+                }
+                _ => {
+                    let matrix = compute_correction_matrix(
+                        &geometry.angles,
+                        &options.ratio,
+                        geometry.center,
+                        geometry.diamond.as_ref(),
+                        vertical_scale.unwrap_or(1.0),
+                    )?;
+                    (matrix, Some(geometry.angles.decompose()))
+                }
+            };
+            debug!(
+                %mode,
+                rotation = decomposition.map(|d| d.rotation),
+                slope = decomposition.map(|d| d.slope),
+                m = ?[
+                    [matrix[(0, 0)], matrix[(0, 1)], matrix[(0, 2)]],
+                    [matrix[(1, 0)], matrix[(1, 1)], matrix[(1, 2)]],
+                    [matrix[(2, 0)], matrix[(2, 1)], matrix[(2, 2)]],
+                ],
+                "correction matrix"
+            );
+            let transformed = if options.pixel_art {
+                apply_projective_transform(&rgba, &matrix, Scale2x)?
+            } else {
+                apply_projective_transform(&rgba, &matrix, filter)?
+            };
+            (matrix, decomposition, transformed)
+        };
+
+        // Crop to content (remove padding), then scale the footprint to a grid tile or the
+        // canvas width (the warp keeps the footprint width), or resize to the target size
+        let cropped = crop_to_content(&transformed, options.detection.alpha_threshold);
+        let canvas = options.canvas.filter(|&(w, h)| w > 0 && h > 0);
+        let tile_width = options.tile_width.filter(|&w| w > 0).map(f64::from);
+        let footprint_width = tile_width.or(canvas.map(|(width, _)| width as f64));
+        let (scale, footprint) = match footprint_width.zip(geometry.diamond) {
+            Some((footprint_width, diamond)) => {
+                // Exactly one tile (or the canvas width), unless multi-tile footprints
+                // are allowed
+                let width = match tile_width {
+                    Some(tile_width) if options.multi_tile => diamond.snapped_width(tile_width),
+                    _ => footprint_width,
+                };
+                debug!(
+                    footprint = diamond.width(),
+                    scaled = width,
+                    "scaling footprint"
+                );
+                (width / diamond.width(), Some(width))
+            }
+            None => {
+                if footprint_width.is_some() {
+                    debug!("no top diamond found, resizing to the output size instead");
+                }
+                let (w, h) = (cropped.width() as f64, cropped.height() as f64);
+                let scale = match canvas {
+                    Some((width, height)) => (width as f64 / w).min(height as f64 / h),
+                    // Whole-pixel scaling keeps every source pixel the same size
+                    None if options.pixel_art => integer_scale(options.size as f64 / w.max(h)),
+                    None => options.size as f64 / w.max(h),
+                };
+                (scale, None)
+            }
+        };
+        let resized = resize_by(&cropped, scale, filter);
+
+        debug!(
+            cropped_width = cropped.width(),
+            cropped_height = cropped.height(),
+            width = resized.width(),
+            height = resized.height(),
+            "cropped and resized"
+        );
+
+        // Follow input points through the warp, the crop and the resize
+        let (_, _, offset_x, offset_y) = compute_output_bounds(&matrix, img.width(), img.height());
+        let (crop_x, crop_y, ..) =
+            find_sprite_bounds(&transformed, alpha_threshold).unwrap_or((0, 0, 0, 0));
+        // Diamond vertices lie on pixel edges, while the warp maps pixel indices and
+        // `resize_by` samples output pixel centers: out = (src + 0.5) * scale - 0.5
+        let to_resized = |(x, y): (f64, f64)| {
+            let (x, y) = transform_point(&matrix, x - 0.5, y - 0.5);
+            let resize = |src: f64| (src + 0.5) * scale - 0.5;
+            (
+                resize(x - offset_x - crop_x as f64) + 0.5,
+                resize(y - offset_y - crop_y as f64) + 0.5,
+            )
+        };
+        let anchor = geometry.diamond.map(|diamond| {
+            to_resized(match options.anchor {
+                AnchorPoint::BottomVertex => diamond.bottom,
+                AnchorPoint::FootprintCenter => diamond.center(),
+            })
+        });
+
+        // Move the anchor onto its canvas pixel; without one, the sprite stands on the bottom edge
+        let mut shift = (0, 0);
+        let mut image = match canvas {
+            Some((width, height)) => {
+                let (x, y) = match (anchor, footprint) {
+                    (Some((ax, ay)), Some(footprint)) => {
+                        let (cx, cy) = self.canvas_point(width, height, footprint);
+                        ((cx - ax).round() as i64, (cy - ay).round() as i64)
+                    }
+                    _ => (
+                        (width as i64 - resized.width() as i64) / 2,
+                        height as i64 - resized.height() as i64,
+                    ),
+                };
+                shift = (x, y);
+                debug!(width, height, x, y, "placing on canvas");
+                let (right, bottom) = (x + resized.width() as i64, y + resized.height() as i64);
+                if x < 0 || y < 0 || right > width as i64 || bottom > height as i64 {
+                    debug!("sprite extends past the canvas, clipping it");
+                }
+                place_on_canvas(&resized, width, height, x, y)
+            }
+            None => resized,
+        };
+        let to_output = |point| {
+            let (x, y) = to_resized(point);
+            (x + shift.0 as f64, y + shift.1 as f64)
+        };
+
+        if let Some(palette) = &palette {
+            debug!(colors = palette.len(), "snapping to the input palette");
+            snap_to_palette(&mut image, palette, alpha_threshold);
+            if let Some(diamond) = &geometry.diamond {
+                let diamond = DetectedDiamond {
+                    left: to_output(diamond.left),
+                    top: to_output(diamond.top),
+                    right: to_output(diamond.right),
+                    bottom: to_output(diamond.bottom),
+                };
+                enforce_stair_steps(&mut image, &diamond, &options.ratio, alpha_threshold);
+            }
+        }
+
+        let anchor = anchor.map(|(x, y)| (x + shift.0 as f64, y + shift.1 as f64));
+        let anchor = anchor.map(|pixel| Anchor {
+            point: options.anchor,
+            pixel,
+            normalized: (
+                pixel.0 / image.width() as f64,
+                pixel.1 / image.height() as f64,
+            ),
+        });
+        if let Some(anchor) = &anchor {
+            debug!(point = %anchor.point, x = anchor.pixel.0, y = anchor.pixel.1, "tile anchor");
+        }
+
+        Ok(CorrectionReport {
+            geometry,
+            target_angle: options.ratio.target_angle_degrees(),
+            skipped,
+            matrix,
+            decomposition,
+            mode,
+            manual_angles: options.angles,
+            low_confidence,
+            height_skipped,
+            input_size: (img.width(), img.height()),
+            transformed_size: transformed.dimensions(),
+            cropped_size: cropped.dimensions(),
+            anchor,
+            image,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_sets_options() {
+        let pipeline = CorrectionPipeline::new()
+            .ratio(IsometricRatio::new(3.0, 1.0))
+            .tolerance(0.5)
+            .size(64)
+            .alpha_threshold(128);
+
+        let options = pipeline.options();
+        assert_eq!(options.ratio.horizontal, 3.0);
+        assert_eq!(options.tolerance, 0.5);
+        assert_eq!(options.size, 64);
+        assert_eq!(options.detection.alpha_threshold, 128);
+    }
+
+    #[test]
+    fn test_manual_angles_skip_line_detection() {
+        // A flat square has no sloping edges, so detection alone would fail
+        let mut img = RgbaImage::new(32, 32);
+        for y in 8..24 {
+            for x in 8..24 {
+                img.put_pixel(x, y, image::Rgba([200, 50, 50, 255]));
+            }
+        }
+        let report = CorrectionPipeline::new()
+            .angles(AngleOverride::from_ratio(&IsometricRatio::new(3.0, 1.0)))
+            .size(16)
+            .run(&DynamicImage::ImageRgba8(img))
+            .unwrap();
+
+        assert!(!report.skipped);
+        assert_eq!(report.geometry.line_count, 0);
+        assert!((report.geometry.angles.right_angle - 18.435).abs() < 0.01);
+    }
+
+    /// Flat 3:1 diamond, 90 px wide
+    fn flat_diamond() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(100, 40, |x, y| {
+            let dx = (x as f64 + 0.5 - 50.0).abs() / 45.0;
+            let dy = (y as f64 + 0.5 - 20.0).abs() / 15.0;
+            let alpha = if dx + dy <= 1.0 { 255 } else { 0 };
+            image::Rgba([90, 140, 60, alpha])
+        }))
+    }
+
+    #[test]
+    fn test_tile_width_scales_footprint() {
+        let pipeline = CorrectionPipeline::new()
+            .angles(AngleOverride::from_ratio(&IsometricRatio::new(3.0, 1.0)))
+            .tile_width(32);
+
+        // The 90 px footprint becomes exactly one tile
+        let report = pipeline.run(&flat_diamond()).unwrap();
+        let diamond = report.geometry.diamond.unwrap();
+        assert!((diamond.width() - 90.0).abs() <= 2.0, "{:?}", diamond);
+        assert!((diamond.height() - 30.0).abs() < 1.5, "{:?}", diamond);
+        assert!(report.image.width().abs_diff(32) <= 1);
+        assert!(report.image.height().abs_diff(16) <= 1);
+
+        // ...or the nearest whole number of tiles
+        let report = pipeline.multi_tile(true).run(&flat_diamond()).unwrap();
+        assert!(report.image.width().abs_diff(96) <= 1);
+        assert!(report.image.height().abs_diff(48) <= 2);
+    }
+
+    #[test]
+    fn test_report_has_the_decomposition_used() {
+        let report = CorrectionPipeline::new()
+            .angles(AngleOverride::new(Some(20.0), Some(24.0)))
+            .run(&flat_diamond())
+            .unwrap();
+        let decomposition = report.decomposition.unwrap();
+        assert!((decomposition.rotation - 2.0).abs() < 1e-9);
+        assert!((decomposition.slope - 22.0).abs() < 1e-9);
+
+        // Nothing is leveled when the warp is skipped or maps the corners directly
+        let pipeline = CorrectionPipeline::new()
+            .angles(AngleOverride::from_ratio(&IsometricRatio::new(2.0, 1.0)));
+        let report = pipeline.run(&flat_diamond()).unwrap();
+        assert!(report.skipped);
+        assert_eq!(report.decomposition, None);
+        let report = pipeline
+            .ratio(IsometricRatio::new(1.0, 1.0))
+            .mode(CorrectionMode::Perspective)
+            .run(&flat_diamond())
+            .unwrap();
+        assert_eq!(report.mode, CorrectionMode::Perspective);
+        assert_eq!(report.decomposition, None);
+    }
+
+    #[test]
+    fn test_height_policy_without_walls_is_reported() {
+        let pipeline = CorrectionPipeline::new()
+            .angles(AngleOverride::from_ratio(&IsometricRatio::new(3.0, 1.0)));
+        assert_eq!(pipeline.run(&flat_diamond()).unwrap().height_skipped, None);
+
+        // Manual angles skip line detection, so there are no vertical edges to go by
+        let report = pipeline
+            .height(HeightPolicy::Cube)
+            .run(&flat_diamond())
+            .unwrap();
+        assert!(!report.skipped);
+        assert_eq!(report.height_skipped, Some(HeightSkipped::NoVerticalEdges));
+
+        let report = pipeline
+            .height(HeightPolicy::Cube)
+            .mode(CorrectionMode::Perspective)
+            .run(&flat_diamond())
+            .unwrap();
+        assert_eq!(report.height_skipped, Some(HeightSkipped::Perspective));
+    }
+
+    #[test]
+    fn test_anchor_follows_correction() {
+        let pipeline = CorrectionPipeline::new()
+            .angles(AngleOverride::from_ratio(&IsometricRatio::new(3.0, 1.0)))
+            .size(128);
+
+        // The 2:1 diamond fills the 128 px wide output, its bottom vertex is at the bottom center
+        let report = pipeline.run(&flat_diamond()).unwrap();
+        let anchor = report.anchor.unwrap();
+        assert_eq!(anchor.point, AnchorPoint::BottomVertex);
+        assert!((anchor.pixel.0 - 64.0).abs() < 2.0, "{:?}", anchor);
+        assert!((anchor.pixel.1 - 64.0).abs() < 2.0, "{:?}", anchor);
+        let height = report.image.height() as f64;
+        assert!((anchor.normalized.1 * height - anchor.pixel.1).abs() < 1e-9);
+
+        let center = pipeline
+            .anchor(AnchorPoint::FootprintCenter)
+            .run(&flat_diamond())
+            .unwrap()
+            .anchor
+            .unwrap();
+        assert!((center.normalized.0 - 0.5).abs() < 0.02, "{:?}", center);
+        assert!((center.pixel.1 - 32.0).abs() < 2.0, "{:?}", center);
+    }
+
+    #[test]
+    fn test_anchor_matches_resized_diamond() {
+        let tile = DynamicImage::ImageRgba8(RgbaImage::from_fn(48, 28, |x, y| {
+            let dx = (x as f64 + 0.5 - 24.0).abs() / 20.0;
+            let dy = (y as f64 + 0.5 - 14.0).abs() / 10.0;
+            let alpha = if dx + dy <= 1.0 { 255 } else { 0 };
+            image::Rgba([90, 140, 60, alpha])
+        }));
+        let two_to_one = AngleOverride::from_ratio(&IsometricRatio::new(2.0, 1.0));
+        let three_to_one = AngleOverride::from_ratio(&IsometricRatio::new(3.0, 1.0));
+
+        // Resized by a fractional factor, without and with a warp
+        for (img, angles) in [(&tile, two_to_one), (&flat_diamond(), three_to_one)] {
+            let report = CorrectionPipeline::new()
+                .angles(angles)
+                .size(150)
+                .run(img)
+                .unwrap();
+            let mut output_angles = report.geometry.angles.clone();
+            two_to_one.apply(&mut output_angles);
+            let bounds = find_sprite_bounds(&report.image, 128).unwrap();
+            let bottom = fit_diamond(&report.image, 128, bounds, &output_angles)
+                .unwrap()
+                .bottom;
+            let anchor = report.anchor.unwrap().pixel;
+            assert!(
+                (anchor.0 - bottom.0).abs() < 0.5,
+                "{:?} {:?}",
+                anchor,
+                bottom
+            );
+            assert!(
+                (anchor.1 - bottom.1).abs() < 0.5,
+                "{:?} {:?}",
+                anchor,
+                bottom
+            );
+        }
+    }
+
+    #[test]
+    fn test_canvas_places_footprint() {
+        let pipeline = CorrectionPipeline::new()
+            .angles(AngleOverride::from_ratio(&IsometricRatio::new(3.0, 1.0)))
+            .canvas(64, 96);
+
+        // The 64x32 footprint rests on the bottom edge
+        let report = pipeline.run(&flat_diamond()).unwrap();
+        assert_eq!(report.image.dimensions(), (64, 96));
+        let anchor = report.anchor.unwrap();
+        assert!((anchor.pixel.0 - 32.0).abs() <= 1.0, "{:?}", anchor);
+        assert!((anchor.pixel.1 - 96.0).abs() <= 1.0, "{:?}", anchor);
+        let bounds = find_sprite_bounds(&report.image, 128).unwrap();
+        assert!(
+            bounds.2.abs_diff(64) <= 1 && bounds.3.abs_diff(32) <= 2,
+            "{:?}",
+            bounds
+        );
+        assert!((bounds.1 + bounds.3).abs_diff(96) <= 1, "{:?}", bounds);
+
+        let report = pipeline.canvas_anchor(32, 48).run(&flat_diamond()).unwrap();
+        let anchor = report.anchor.unwrap();
+        assert!((anchor.pixel.1 - 48.0).abs() <= 1.0, "{:?}", anchor);
+    }
+
+    #[test]
+    fn test_canvas_scales_large_footprint() {
+        // A 90 px footprint on a 24 px canvas is scaled down to it, not rounded to 4 tiles
+        let report = CorrectionPipeline::new()
+            .angles(AngleOverride::from_ratio(&IsometricRatio::new(3.0, 1.0)))
+            .canvas(24, 40)
+            .run(&flat_diamond())
+            .unwrap();
+
+        assert_eq!(report.image.dimensions(), (24, 40));
+        let bounds = find_sprite_bounds(&report.image, 128).unwrap();
+        assert!(bounds.2.abs_diff(24) <= 1, "{:?}", bounds);
+        assert!(bounds.3.abs_diff(12) <= 1, "{:?}", bounds);
+    }
+
+    #[test]
+    fn test_pixel_art_keeps_palette() {
+        let report = CorrectionPipeline::new()
+            .angles(AngleOverride::from_ratio(&IsometricRatio::new(3.0, 1.0)))
+            .pixel_art(true)
+            .size(128)
+            .run(&flat_diamond())
+            .unwrap();
+
+        // Only the input color and full transparency, at a whole-pixel scale
+        assert!(report
+            .image
+            .pixels()
+            .all(|p| p.0 == [90, 140, 60, 255] || p.0 == [0, 0, 0, 0]));
+        assert_eq!(report.image.width(), report.cropped_size.0);
+    }
+
+    #[test]
+    fn test_low_confidence_policies() {
+        let detected = DetectedAngles::new(-30.0, 20.0, 0.8, 0.0);
+        let check = |policy| {
+            let mut angles = detected.clone();
+            let result = CorrectionPipeline::new()
+                .ratio(IsometricRatio::new(3.0, 1.0))
+                .on_low_confidence(policy)
+                .check_confidence(&mut angles);
+            (result, angles)
+        };
+
+        let (result, angles) = check(LowConfidencePolicy::Mirror);
+        assert_eq!(result.unwrap().unwrap().side, Side::Right);
+        assert_eq!(angles.right_angle, 30.0);
+
+        // Warn assumes the requested ratio, not 2:1
+        let (_, angles) = check(LowConfidencePolicy::Warn);
+        assert!((angles.right_angle - 18.435).abs() < 0.01);
+        assert_eq!(angles.left_angle, -30.0);
+
+        let (result, _) = check(LowConfidencePolicy::Fail);
+        assert!(matches!(
+            result,
+            Err(TrueIsoError::LowConfidence {
+                side: Side::Right,
+                ..
+            })
+        ));
+
+        let mut confident = DetectedAngles::new(-30.0, 20.0, 0.8, 0.9);
+        let result = CorrectionPipeline::new().check_confidence(&mut confident);
+        assert!(result.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_analyze_applies_low_confidence_policy() {
+        // Upper left quarter of a diamond: only one of the two slopes is there
+        let mut img = flat_diamond().to_rgba8();
+        for pixel in img
+            .enumerate_pixels_mut()
+            .filter(|(x, y, _)| *x >= 50 || *y >= 20)
+        {
+            pixel.2 .0 = [0, 0, 0, 0];
+        }
+        let img = DynamicImage::ImageRgba8(img);
+
+        let analysis = CorrectionPipeline::new().analyze(&img).unwrap();
+        assert!(analysis.is_refused());
+        assert_eq!(analysis.low_confidence.unwrap().side, Side::Right);
+
+        let result = CorrectionPipeline::new()
+            .on_low_confidence(LowConfidencePolicy::Fail)
+            .analyze(&img);
+        assert!(matches!(result, Err(TrueIsoError::LowConfidence { .. })));
+
+        // Mirrored, the 3:1 slopes are out of tolerance, as they are when correcting
+        let analysis = CorrectionPipeline::new()
+            .on_low_confidence(LowConfidencePolicy::Mirror)
+            .analyze(&img)
+            .unwrap();
+        assert!(!analysis.is_refused() && !analysis.within_tolerance);
+        assert_eq!(analysis.geometry.angles.right_angle, 18.0);
+    }
+
+    #[test]
+    #[ignore = "slow: detects full-size example images, run with --ignored"]
+    fn test_painted_examples_are_confident() {
+        // Thick outlines and busy textures (carbon weave, circuit boards); these used to
+        // fall below the default minimum confidence and were skipped
+        for name in [
+            "ac533f9c-4ade-45bc-af1c-7b3db05602fc",
+            "b3b1220a-583c-48df-9fbb-bd3abba571d8",
+            "c73bdd6d-c9a4-4d0b-b2dd-57ea4c7c3bea",
+        ] {
+            let path = format!("{}/examples/{}.png", env!("CARGO_MANIFEST_DIR"), name);
+            let img = image::open(&path).unwrap();
+            let analysis = CorrectionPipeline::new().analyze(&img).unwrap();
+            assert!(
+                analysis.low_confidence.is_none(),
+                "{}: {:?}",
+                name,
+                analysis.geometry.angles
+            );
+        }
+    }
+
+    #[test]
+    fn test_analyze_on_transparent_image_fails() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(16, 16));
+        let result = CorrectionPipeline::new().analyze(&img);
+        assert!(matches!(result, Err(TrueIsoError::EmptySprite { .. })));
+    }
+
+    #[test]
+    fn test_run_on_transparent_image_fails() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(16, 16));
+        let result = CorrectionPipeline::new().run(&img);
+        assert!(matches!(result, Err(TrueIsoError::EmptySprite { .. })));
+    }
+}
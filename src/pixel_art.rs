@@ -0,0 +1,277 @@
+use image::{Rgba, RgbaImage};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::geometry::{DetectedDiamond, IsometricRatio};
+use crate::transform::{Interpolation, Interpolator};
+
+/// The distinct colors of a sprite
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<Rgba<u8>>,
+}
+
+impl Palette {
+    /// Collect every color with at least `alpha_threshold` alpha
+    pub fn extract(img: &RgbaImage, alpha_threshold: u8) -> Self {
+        let colors: BTreeSet<[u8; 4]> = img
+            .pixels()
+            .filter(|pixel| pixel[3] >= alpha_threshold.max(1))
+            .map(|pixel| pixel.0)
+            .collect();
+        Self {
+            colors: colors.into_iter().map(Rgba).collect(),
+        }
+    }
+
+    pub fn colors(&self) -> &[Rgba<u8>] {
+        &self.colors
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    /// Palette color closest to `color` (squared RGBA distance)
+    pub fn nearest(&self, color: Rgba<u8>) -> Rgba<u8> {
+        let distance = |other: &Rgba<u8>| -> u32 {
+            (0..4)
+                .map(|c| (color[c] as i32 - other[c] as i32).pow(2) as u32)
+                .sum()
+        };
+        self.colors
+            .iter()
+            .min_by_key(|other| distance(other))
+            .copied()
+            .unwrap_or(color)
+    }
+}
+
+/// Replace every pixel by its closest palette color
+///
+/// Pixels below `alpha_threshold` become fully transparent instead.
+pub fn snap_to_palette(img: &mut RgbaImage, palette: &Palette, alpha_threshold: u8) {
+    let mut snapped: HashMap<[u8; 4], Rgba<u8>> = HashMap::new();
+    for pixel in img.pixels_mut() {
+        *pixel = if pixel[3] < alpha_threshold.max(1) {
+            Rgba([0, 0, 0, 0])
+        } else {
+            *snapped
+                .entry(pixel.0)
+                .or_insert_with(|| palette.nearest(*pixel))
+        };
+    }
+}
+
+/// Nearest-neighbour sampling of the Scale2x (EPX) upscaled image, computed on the fly
+///
+/// Each source pixel is split into four quadrants; a quadrant takes the color of two
+/// matching neighbours that meet at its corner, so diagonal runs stay smooth instead of
+/// breaking into uneven steps (the idea behind RotSprite). Only source colors come out.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Scale2x;
+
+impl Interpolator for Scale2x {
+    fn support(&self) -> f64 {
+        Interpolation::Nearest.support()
+    }
+
+    fn weight(&self, t: f64) -> f64 {
+        Interpolation::Nearest.weight(t)
+    }
+
+    fn sample(
+        &self,
+        premultiplied: &[[f64; 4]],
+        width: u32,
+        height: u32,
+        x: f64,
+        y: f64,
+    ) -> [f64; 4] {
+        let px = (x + 0.5).floor().clamp(0.0, width as f64 - 1.0) as i64;
+        let py = (y + 0.5).floor().clamp(0.0, height as f64 - 1.0) as i64;
+        let at = |dx: i64, dy: i64| {
+            let sx = (px + dx).clamp(0, width as i64 - 1);
+            let sy = (py + dy).clamp(0, height as i64 - 1);
+            premultiplied[(sy * width as i64 + sx) as usize]
+        };
+
+        // Neighbours next to the quadrant the position falls into, and the opposite ones
+        let (left, right) = (at(-1, 0), at(1, 0));
+        let (up, down) = (at(0, -1), at(0, 1));
+        let (horizontal, opposite_horizontal) = if x >= px as f64 {
+            (right, left)
+        } else {
+            (left, right)
+        };
+        let (vertical, opposite_vertical) = if y >= py as f64 {
+            (down, up)
+        } else {
+            (up, down)
+        };
+
+        if horizontal == vertical
+            && horizontal != opposite_vertical
+            && vertical != opposite_horizontal
+        {
+            vertical
+        } else {
+            at(0, 0)
+        }
+    }
+}
+
+/// Scale factor rounded to a whole multiple (or a whole fraction, when shrinking)
+///
+/// Keeps every source pixel the same size in the output.
+pub fn integer_scale(scale: f64) -> f64 {
+    if scale >= 1.0 {
+        scale.floor()
+    } else if scale > 0.0 {
+        1.0 / (1.0 / scale).ceil()
+    } else {
+        scale
+    }
+}
+
+/// Redraw the silhouette along the top diamond's edges as clean pixel stair steps
+///
+/// Along a 2:1 edge every row advances exactly two pixels across ("2 across, 1 down").
+/// The upper edges start at the topmost pixel near the top vertex, the lower ones at the
+/// lowest pixel below it (the bottom of the walls for a block). Only the rows right at
+/// the edge are touched: a single stray pixel outside the line is cleared, and a gap on
+/// the line is filled from the pixel next to it on the inside.
+pub fn enforce_stair_steps(
+    img: &mut RgbaImage,
+    diamond: &DetectedDiamond,
+    ratio: &IsometricRatio,
+    alpha_threshold: u8,
+) {
+    let (width, height) = (img.width() as i64, img.height() as i64);
+    let center = diamond.top.0.round() as i64;
+    let half = (diamond.width() / 2.0).round() as i64;
+    if half < 2 || center < 1 || center >= width {
+        return;
+    }
+
+    let threshold = alpha_threshold.max(1);
+    let opaque = |img: &RgbaImage, x: i64, y: i64| {
+        (0..width).contains(&x)
+            && (0..height).contains(&y)
+            && img.get_pixel(x as u32, y as u32)[3] >= threshold
+    };
+    let slope = ratio.vertical / ratio.horizontal;
+    // Rows an edge column lies below (above, for the lower edges) the vertex
+    let drop = |x: i64| {
+        let distance = if x < center {
+            center - 1 - x
+        } else {
+            x - center
+        };
+        (distance as f64 * slope).floor() as i64
+    };
+    let vertex_columns = [center - 1, center];
+
+    let near_top = diamond.top.1.round() as i64;
+    let top =
+        (near_top - 2..=near_top + 2).find(|&y| vertex_columns.iter().any(|&x| opaque(img, x, y)));
+    let bottom = (0..height)
+        .rev()
+        .find(|&y| vertex_columns.iter().any(|&x| opaque(img, x, y)));
+
+    // (row of the vertex, direction pointing outside the sprite)
+    let edges = [top.map(|y| (y, -1)), bottom.map(|y| (y, 1))];
+    for (vertex, outside) in edges.into_iter().flatten() {
+        for x in (center - half).max(0)..(center + half).min(width) {
+            let y = vertex - outside * drop(x);
+            let (beyond, inside) = (y + outside, y - outside);
+            if opaque(img, x, beyond) && !opaque(img, x, beyond + outside) {
+                img.put_pixel(x as u32, beyond as u32, Rgba([0, 0, 0, 0]));
+            }
+            if (0..height).contains(&y) && !opaque(img, x, y) && opaque(img, x, inside) {
+                let fill = *img.get_pixel(x as u32, inside as u32);
+                img.put_pixel(x as u32, y as u32, fill);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREEN: Rgba<u8> = Rgba([60, 160, 60, 255]);
+
+    #[test]
+    fn test_palette_snapping() {
+        let mut img = RgbaImage::from_fn(4, 1, |x, _| match x {
+            0 => Rgba([200, 40, 40, 255]),
+            1 => GREEN,
+            _ => Rgba([0, 0, 0, 0]),
+        });
+        let palette = Palette::extract(&img, 10);
+        assert_eq!(palette.len(), 2);
+
+        img.put_pixel(2, 0, Rgba([190, 50, 45, 250]));
+        img.put_pixel(3, 0, Rgba([60, 160, 60, 5]));
+        snap_to_palette(&mut img, &palette, 10);
+        assert_eq!(*img.get_pixel(2, 0), Rgba([200, 40, 40, 255]));
+        assert_eq!(*img.get_pixel(3, 0), Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_scale2x_smooths_diagonals() {
+        // Staircase: a 2x upscale fills the inner corners of the steps
+        let mut premultiplied = vec![[0.0; 4]; 9];
+        for &(x, y) in &[(0, 0), (0, 1), (1, 1), (0, 2), (1, 2), (2, 2)] {
+            premultiplied[y * 3 + x] = [1.0, 1.0, 1.0, 255.0];
+        }
+        let sample = |x, y| Scale2x.sample(&premultiplied, 3, 3, x, y)[3];
+        // The empty pixel (1, 0) gets its lower-left quadrant filled...
+        assert_eq!(sample(0.75, 0.25), 255.0);
+        assert_eq!(sample(0.75, -0.25), 0.0);
+        assert_eq!(sample(1.25, 0.25), 0.0);
+        // ...and the outer corner of the filled pixel (1, 1) is cut
+        assert_eq!(sample(1.25, 0.75), 0.0);
+        assert_eq!(sample(0.75, 1.25), 255.0);
+    }
+
+    #[test]
+    fn test_integer_scale() {
+        assert_eq!(integer_scale(3.7), 3.0);
+        assert_eq!(integer_scale(1.0), 1.0);
+        assert_eq!(integer_scale(0.3), 0.25);
+    }
+
+    #[test]
+    fn test_stair_steps() {
+        // A 16x8 diamond with a stray pixel above the upper left edge and a gap in it
+        let inside = |x: i64, y: i64| {
+            let distance = if x < 8 { 7 - x } else { x - 8 };
+            let (top, bottom) = (distance / 2, 7 - distance / 2);
+            (top..=bottom).contains(&y)
+        };
+        let mut img = RgbaImage::from_fn(16, 8, |x, y| {
+            if inside(x as i64, y as i64) {
+                GREEN
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        let clean = img.clone();
+        img.put_pixel(2, 1, GREEN);
+        img.put_pixel(3, 2, Rgba([0, 0, 0, 0]));
+
+        let diamond = DetectedDiamond {
+            left: (0.0, 4.0),
+            top: (8.0, 0.0),
+            right: (16.0, 4.0),
+            bottom: (8.0, 8.0),
+        };
+        enforce_stair_steps(&mut img, &diamond, &IsometricRatio::default(), 10);
+        assert_eq!(img, clean);
+    }
+}
@@ -0,0 +1,296 @@
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, TrueIsoError};
+use crate::geometry::{AngleDecomposition, AngleOverride, DetectedAngles, DetectedDiamond};
+use crate::pipeline::{Anchor, CorrectionMode, CorrectionReport, HeightSkipped, LowConfidence};
+
+/// Width and height of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl From<(u32, u32)> for Size {
+    fn from((width, height): (u32, u32)) -> Self {
+        Self { width, height }
+    }
+}
+
+/// Sprite bounding box in input pixel coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Bounds {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl From<(u32, u32, u32, u32)> for Bounds {
+    fn from((x, y, width, height): (u32, u32, u32, u32)) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// What the pipeline found and did for one image
+#[derive(Debug, Clone, Serialize)]
+pub struct CorrectionRecord {
+    /// Input image dimensions
+    pub input_size: Size,
+    /// Non-transparent bounding box of the sprite
+    pub sprite_bounds: Bounds,
+    /// Detected angles and their confidences
+    pub angles: DetectedAngles,
+    /// Rotation and level slope the affine correction removed (`null` if the warp was
+    /// skipped or perspective)
+    pub decomposition: Option<AngleDecomposition>,
+    /// Top-face corners in input pixel coordinates (`null` if they could not be located)
+    pub diamond: Option<DetectedDiamond>,
+    /// Number of Hough lines detected
+    pub line_count: usize,
+    /// Target angle in degrees
+    pub target_angle: f64,
+    /// True if the angles were already within tolerance and no warp was applied
+    pub skipped: bool,
+    /// Forward correction matrix, row-major
+    pub matrix: [[f64; 3]; 3],
+    /// Correction mode that was applied
+    pub mode: CorrectionMode,
+    /// Manual angles that replaced detection
+    #[serde(skip_serializing_if = "AngleOverride::is_empty")]
+    pub manual_angles: AngleOverride,
+    /// Low-confidence policy that fired (`null` if both sides were confident)
+    pub low_confidence: Option<LowConfidence>,
+    /// Why the height policy left the walls unchanged (`null` if it was applied)
+    pub height_skipped: Option<HeightSkipped>,
+    /// Final image dimensions
+    pub output_size: Size,
+    /// Tile anchor in the output image (`null` if no top diamond was found)
+    pub anchor: Option<Anchor>,
+}
+
+impl From<&CorrectionReport> for CorrectionRecord {
+    fn from(report: &CorrectionReport) -> Self {
+        let m = &report.matrix;
+        Self {
+            input_size: report.input_size.into(),
+            sprite_bounds: report.geometry.bounds.into(),
+            angles: report.geometry.angles.clone(),
+            decomposition: report.decomposition,
+            diamond: report.geometry.diamond,
+            line_count: report.geometry.line_count,
+            target_angle: report.target_angle,
+            skipped: report.skipped,
+            matrix: [
+                [m[(0, 0)], m[(0, 1)], m[(0, 2)]],
+                [m[(1, 0)], m[(1, 1)], m[(1, 2)]],
+                [m[(2, 0)], m[(2, 1)], m[(2, 2)]],
+            ],
+            mode: report.mode,
+            manual_angles: report.manual_angles,
+            low_confidence: report.low_confidence,
+            height_skipped: report.height_skipped,
+            output_size: report.image.dimensions().into(),
+            anchor: report.anchor,
+        }
+    }
+}
+
+/// Which sprite of a split sheet a record belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SpriteRecord {
+    /// Position in reading order, counting from 1
+    pub index: usize,
+    /// Bounding box of the sprite in the sheet
+    pub bounds: Bounds,
+}
+
+/// One entry of a `--report` file
+///
+/// Failed files carry an `error` message and no correction fields. With `--split`
+/// there is one entry per sprite, each with its `sprite` index and sheet bounds.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileRecord {
+    pub input: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sprite: Option<SpriteRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(flatten)]
+    pub correction: Option<CorrectionRecord>,
+}
+
+impl FileRecord {
+    /// Record for a successfully processed file
+    pub fn corrected(input: &Path, output: &Path, report: &CorrectionReport) -> Self {
+        Self {
+            input: input.to_path_buf(),
+            sprite: None,
+            output: Some(output.to_path_buf()),
+            error: None,
+            correction: Some(report.into()),
+        }
+    }
+
+    /// Record for a file that could not be processed
+    pub fn failed(input: &Path, error: impl Into<String>) -> Self {
+        Self {
+            input: input.to_path_buf(),
+            sprite: None,
+            output: None,
+            error: Some(error.into()),
+            correction: None,
+        }
+    }
+
+    /// Mark the record as belonging to one sprite of a split sheet
+    pub fn with_sprite(mut self, index: usize, bounds: impl Into<Bounds>) -> Self {
+        self.sprite = Some(SpriteRecord {
+            index,
+            bounds: bounds.into(),
+        });
+        self
+    }
+}
+
+/// Contents of an `--anchor-file` sidecar
+#[derive(Debug, Clone, Serialize)]
+pub struct AnchorRecord {
+    /// File name of the output image
+    pub image: PathBuf,
+    pub size: Size,
+    /// Tile anchor (`null` if no top diamond was found)
+    pub anchor: Option<Anchor>,
+}
+
+impl AnchorRecord {
+    pub fn new(output: &Path, report: &CorrectionReport) -> Self {
+        Self {
+            image: output.file_name().map(PathBuf::from).unwrap_or_default(),
+            size: report.image.dimensions().into(),
+            anchor: report.anchor,
+        }
+    }
+}
+
+/// Write an anchor sidecar to `path`
+pub fn write_anchor_file(path: &Path, record: &AnchorRecord) -> Result<()> {
+    write_json(path, record)
+}
+
+/// Write records as a JSON array to `path`, or to stdout if `path` is `-`
+pub fn write_report(path: &Path, records: &[FileRecord]) -> Result<()> {
+    write_json(path, records)
+}
+
+/// Write pretty-printed JSON to `path`, or to stdout if `path` is `-`
+fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<()> {
+    let io_error = |source| TrueIsoError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let mut json = serde_json::to_vec_pretty(value).map_err(|err| io_error(err.into()))?;
+    json.push(b'\n');
+
+    if path == Path::new("-") {
+        std::io::stdout().lock().write_all(&json).map_err(io_error)
+    } else {
+        std::fs::write(path, json).map_err(io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Side;
+    use crate::pipeline::{AnchorPoint, LowConfidencePolicy};
+
+    #[test]
+    fn test_failed_record_omits_correction() {
+        let record = FileRecord::failed(Path::new("tile.png"), "image is fully transparent");
+        let json = serde_json::to_value(&record).unwrap();
+
+        assert_eq!(json["input"], "tile.png");
+        assert_eq!(json["error"], "image is fully transparent");
+        assert!(json.get("output").is_none());
+        assert!(json.get("sprite").is_none());
+        assert!(json.get("angles").is_none());
+    }
+
+    #[test]
+    fn test_corrected_record_fields() {
+        let report = CorrectionReport {
+            geometry: crate::detection::DetectedGeometry {
+                angles: DetectedAngles::new(-24.0, 28.0, 0.8, 0.6),
+                bounds: (2, 3, 40, 20),
+                center: (22.0, 13.0),
+                line_count: 7,
+                diamond: Some(DetectedDiamond {
+                    left: (2.0, 13.0),
+                    top: (22.0, 3.0),
+                    right: (42.0, 13.0),
+                    bottom: (22.0, 23.0),
+                }),
+            },
+            target_angle: 26.565,
+            skipped: false,
+            matrix: nalgebra::Matrix3::identity(),
+            decomposition: Some(AngleDecomposition {
+                rotation: 2.0,
+                slope: 26.0,
+            }),
+            mode: CorrectionMode::Affine,
+            manual_angles: AngleOverride::default(),
+            low_confidence: Some(LowConfidence {
+                side: Side::Right,
+                policy: LowConfidencePolicy::Mirror,
+            }),
+            height_skipped: Some(HeightSkipped::NoVerticalEdges),
+            input_size: (64, 32),
+            transformed_size: (64, 32),
+            cropped_size: (40, 20),
+            anchor: Some(Anchor {
+                point: AnchorPoint::BottomVertex,
+                pixel: (64.0, 48.0),
+                normalized: (0.5, 0.75),
+            }),
+            image: image::RgbaImage::new(128, 64),
+        };
+        let record =
+            FileRecord::corrected(Path::new("a.png"), Path::new("a_corrected.png"), &report);
+        let json = serde_json::to_value(&record).unwrap();
+
+        assert_eq!(json["output"], "a_corrected.png");
+        assert_eq!(json["sprite_bounds"]["width"], 40);
+        assert_eq!(json["angles"]["left_angle"], -24.0);
+        assert_eq!(json["decomposition"]["rotation"], 2.0);
+        assert_eq!(json["decomposition"]["slope"], 26.0);
+        assert_eq!(json["line_count"], 7);
+        assert_eq!(json["diamond"]["top"], serde_json::json!([22.0, 3.0]));
+        assert_eq!(json["matrix"][2][2], 1.0);
+        assert_eq!(json["mode"], "affine");
+        assert_eq!(json["output_size"]["height"], 64);
+        assert!(json.get("error").is_none());
+        assert!(json.get("manual_angles").is_none());
+        assert_eq!(json["low_confidence"]["side"], "right");
+        assert_eq!(json["low_confidence"]["policy"], "mirror");
+        assert_eq!(json["height_skipped"], "no-vertical-edges");
+        assert_eq!(json["anchor"]["point"], "bottom-vertex");
+        assert_eq!(json["anchor"]["normalized"], serde_json::json!([0.5, 0.75]));
+
+        let sidecar = AnchorRecord::new(Path::new("out/a_corrected.png"), &report);
+        let json = serde_json::to_value(&sidecar).unwrap();
+        assert_eq!(json["image"], "a_corrected.png");
+        assert_eq!(json["anchor"]["pixel"], serde_json::json!([64.0, 48.0]));
+    }
+}
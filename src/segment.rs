@@ -0,0 +1,459 @@
+use image::{imageops, GrayImage, Luma, Rgba, RgbaImage};
+use imageproc::region_labelling::{connected_components, Connectivity};
+use tracing::debug;
+
+use crate::detection::{find_sprite_bounds, SpriteBounds};
+use crate::error::{Result, TrueIsoError};
+
+/// Components with fewer opaque pixels than this are treated as stray noise
+const MIN_SPRITE_PIXELS: u32 = 16;
+
+/// Transparent border added around each extracted sprite (edge detection needs some room)
+const SPRITE_PADDING: u32 = 8;
+
+/// One sprite cut out of a sheet
+#[derive(Debug, Clone)]
+pub struct Sprite {
+    /// Bounding box of the sprite in the sheet (x, y, width, height)
+    pub bounds: SpriteBounds,
+    /// The sprite alone on a transparent canvas, padded on every side
+    pub image: RgbaImage,
+}
+
+/// Cell layout of a grid-based sprite sheet
+///
+/// Either the number of columns and rows or the cell size must be given; the other one
+/// follows from the image size. Cells are `spacing` pixels apart, inside a `margin`
+/// wide border.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GridLayout {
+    /// Number of columns and rows
+    pub grid: Option<(u32, u32)>,
+    /// Cell width and height in pixels
+    pub cell: Option<(u32, u32)>,
+    /// Border around the whole grid in pixels
+    pub margin: u32,
+    /// Gap between neighbouring cells in pixels
+    pub spacing: u32,
+}
+
+impl GridLayout {
+    /// Columns, rows, cell width and cell height for an image of the given size
+    pub fn resolve(&self, width: u32, height: u32) -> Result<(u32, u32, u32, u32)> {
+        let is_empty = |size: Option<(u32, u32)>| size.is_some_and(|(a, b)| a == 0 || b == 0);
+        if is_empty(self.grid) || is_empty(self.cell) {
+            return Err(TrueIsoError::InvalidConfig(
+                "grid and cell sizes must be positive",
+            ));
+        }
+
+        let inner = |size: u32| size.saturating_sub(2 * self.margin);
+        let (columns, rows, cell_width, cell_height) = match (self.grid, self.cell) {
+            (Some((columns, rows)), Some((cell_width, cell_height))) => {
+                (columns, rows, cell_width, cell_height)
+            }
+            (Some((columns, rows)), None) => {
+                let cell = |size: u32, count: u32| {
+                    inner(size).saturating_sub((count - 1) * self.spacing) / count
+                };
+                (columns, rows, cell(width, columns), cell(height, rows))
+            }
+            (None, Some((cell_width, cell_height))) => {
+                let count =
+                    |size: u32, cell: u32| (inner(size) + self.spacing) / (cell + self.spacing);
+                (
+                    count(width, cell_width),
+                    count(height, cell_height),
+                    cell_width,
+                    cell_height,
+                )
+            }
+            (None, None) => {
+                return Err(TrueIsoError::InvalidConfig(
+                    "grid needs a cell count or a cell size",
+                ));
+            }
+        };
+
+        let extent =
+            |count: u32, cell: u32| 2 * self.margin + count * cell + (count - 1) * self.spacing;
+        if columns == 0 || rows == 0 || cell_width == 0 || cell_height == 0 {
+            return Err(TrueIsoError::InvalidConfig(
+                "grid cells do not fit the image",
+            ));
+        }
+        if extent(columns, cell_width) > width || extent(rows, cell_height) > height {
+            return Err(TrueIsoError::InvalidConfig(
+                "grid cells do not fit the image",
+            ));
+        }
+        Ok((columns, rows, cell_width, cell_height))
+    }
+}
+
+/// Cut a grid-based sheet into its cells, in reading order
+///
+/// Cells without any pixel at or above the alpha threshold are `None`.
+pub fn slice_grid(
+    img: &RgbaImage,
+    layout: &GridLayout,
+    alpha_threshold: u8,
+) -> Result<Vec<Option<Sprite>>> {
+    let (columns, rows, cell_width, cell_height) = layout.resolve(img.width(), img.height())?;
+    debug!(
+        columns,
+        rows, cell_width, cell_height, "slicing sprite sheet"
+    );
+
+    let mut cells = Vec::with_capacity((columns * rows) as usize);
+    for row in 0..rows {
+        for column in 0..columns {
+            let x = layout.margin + column * (cell_width + layout.spacing);
+            let y = layout.margin + row * (cell_height + layout.spacing);
+            let cell = imageops::crop_imm(img, x, y, cell_width, cell_height).to_image();
+            if find_sprite_bounds(&cell, alpha_threshold).is_none() {
+                cells.push(None);
+                continue;
+            }
+
+            let mut image = RgbaImage::new(
+                cell_width + 2 * SPRITE_PADDING,
+                cell_height + 2 * SPRITE_PADDING,
+            );
+            imageops::replace(
+                &mut image,
+                &cell,
+                SPRITE_PADDING as i64,
+                SPRITE_PADDING as i64,
+            );
+            cells.push(Some(Sprite {
+                bounds: (x, y, cell_width, cell_height),
+                image,
+            }));
+        }
+    }
+    Ok(cells)
+}
+
+/// Pack images into a sheet of `columns` uniform cells, in reading order
+///
+/// The cell size is the largest width and height among the images. Each image is
+/// centered horizontally and rests on the bottom of its cell, so the footprints of
+/// isometric tiles line up; `None` leaves a cell empty.
+pub fn pack_grid(images: &[Option<RgbaImage>], columns: u32) -> RgbaImage {
+    let cell_width = images
+        .iter()
+        .flatten()
+        .map(|img| img.width())
+        .max()
+        .unwrap_or(0);
+    let cell_height = images
+        .iter()
+        .flatten()
+        .map(|img| img.height())
+        .max()
+        .unwrap_or(0);
+    let columns = columns.max(1);
+    let rows = (images.len() as u32).div_ceil(columns);
+
+    let mut sheet = RgbaImage::new(columns * cell_width, rows * cell_height);
+    for (i, image) in images.iter().enumerate() {
+        let Some(image) = image else {
+            continue;
+        };
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        let x = column * cell_width + (cell_width - image.width()) / 2;
+        let y = row * cell_height + cell_height - image.height();
+        imageops::replace(&mut sheet, image, x as i64, y as i64);
+    }
+    sheet
+}
+
+/// Split an image into its separate sprites, in reading order
+///
+/// Sprites are the 8-connected components of the alpha mask. A component inside another
+/// one's bounding box is merged into it, so detached details (a floating leaf, a shadow)
+/// stay with their sprite; tiny leftovers are dropped as noise. Pixels of other sprites
+/// inside a sprite's bounding box are cleared.
+pub fn split_sprites(img: &RgbaImage, alpha_threshold: u8) -> Vec<Sprite> {
+    let mask = GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        Luma([u8::from(img.get_pixel(x, y)[3] >= alpha_threshold)])
+    });
+    let labels = connected_components(&mask, Connectivity::Eight, Luma([0u8]));
+
+    // Bounding box and pixel count per label (label 0 is the background)
+    let mut regions: Vec<Region> = Vec::new();
+    for (x, y, label) in labels.enumerate_pixels() {
+        let label = label[0] as usize;
+        if label == 0 {
+            continue;
+        }
+        if regions.len() < label {
+            regions.resize(label, Region::EMPTY);
+        }
+        regions[label - 1].add(x, y);
+    }
+
+    let groups = merge_contained(&regions);
+    let mut sprites: Vec<(Region, Vec<usize>)> = groups
+        .into_iter()
+        .filter(|(region, _)| region.pixels >= MIN_SPRITE_PIXELS)
+        .collect();
+    sort_reading_order(&mut sprites);
+
+    debug!(
+        components = regions.len(),
+        sprites = sprites.len(),
+        "segmented sprite sheet"
+    );
+
+    sprites
+        .into_iter()
+        .map(|(region, members)| {
+            let bounds = region.bounds();
+            let (x0, y0, width, height) = bounds;
+            let mut image = RgbaImage::new(width + 2 * SPRITE_PADDING, height + 2 * SPRITE_PADDING);
+            for y in y0..y0 + height {
+                for x in x0..x0 + width {
+                    let label = labels.get_pixel(x, y)[0] as usize;
+                    // Faint pixels below the threshold (anti-aliasing) stay with the sprite
+                    let pixel = if label == 0 || members.contains(&(label - 1)) {
+                        *img.get_pixel(x, y)
+                    } else {
+                        Rgba([0, 0, 0, 0])
+                    };
+                    image.put_pixel(x - x0 + SPRITE_PADDING, y - y0 + SPRITE_PADDING, pixel);
+                }
+            }
+            Sprite { bounds, image }
+        })
+        .collect()
+}
+
+/// Bounding box and size of a set of pixels
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    min: (u32, u32),
+    max: (u32, u32),
+    pixels: u32,
+}
+
+impl Region {
+    const EMPTY: Self = Self {
+        min: (u32::MAX, u32::MAX),
+        max: (0, 0),
+        pixels: 0,
+    };
+
+    fn add(&mut self, x: u32, y: u32) {
+        self.min = (self.min.0.min(x), self.min.1.min(y));
+        self.max = (self.max.0.max(x), self.max.1.max(y));
+        self.pixels += 1;
+    }
+
+    fn area(&self) -> u64 {
+        let (_, _, width, height) = self.bounds();
+        width as u64 * height as u64
+    }
+
+    fn contains(&self, other: &Region) -> bool {
+        self.min.0 <= other.min.0
+            && self.min.1 <= other.min.1
+            && other.max.0 <= self.max.0
+            && other.max.1 <= self.max.1
+    }
+
+    fn bounds(&self) -> SpriteBounds {
+        (
+            self.min.0,
+            self.min.1,
+            self.max.0 - self.min.0 + 1,
+            self.max.1 - self.min.1 + 1,
+        )
+    }
+}
+
+/// Group regions, folding each one into the largest region whose bounding box contains it
+///
+/// Only containment merges: interlocking isometric tiles have overlapping boxes, but
+/// neither contains the other.
+fn merge_contained(regions: &[Region]) -> Vec<(Region, Vec<usize>)> {
+    let mut order: Vec<usize> = (0..regions.len())
+        .filter(|&i| regions[i].pixels > 0)
+        .collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(regions[i].area()));
+
+    let mut groups: Vec<(Region, Vec<usize>)> = Vec::new();
+    for i in order {
+        let region = regions[i];
+        match groups.iter_mut().find(|(group, _)| group.contains(&region)) {
+            Some((group, members)) => {
+                group.pixels += region.pixels;
+                members.push(i);
+            }
+            None => groups.push((region, vec![i])),
+        }
+    }
+    groups
+}
+
+/// Sort sprites into rows (sprites whose vertical extents overlap), then left to right
+fn sort_reading_order(sprites: &mut [(Region, Vec<usize>)]) {
+    sprites.sort_by_key(|(region, _)| region.min.1);
+
+    let mut row = 0;
+    let mut row_bottom = None;
+    let mut rows = Vec::with_capacity(sprites.len());
+    for (region, _) in sprites.iter() {
+        match row_bottom {
+            Some(bottom) if region.min.1 <= bottom => {
+                row_bottom = Some(region.max.1.max(bottom));
+            }
+            Some(_) => {
+                row += 1;
+                row_bottom = Some(region.max.1);
+            }
+            None => row_bottom = Some(region.max.1),
+        }
+        rows.push(row);
+    }
+
+    let mut keyed: Vec<_> = rows.into_iter().zip(sprites.iter().cloned()).collect();
+    keyed.sort_by_key(|(row, (region, _))| (*row, region.min.0));
+    for (slot, (_, sprite)) in sprites.iter_mut().zip(keyed) {
+        *slot = sprite;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(img: &mut RgbaImage, x0: u32, y0: u32, width: u32, height: u32) {
+        for y in y0..y0 + height {
+            for x in x0..x0 + width {
+                img.put_pixel(x, y, Rgba([120, 160, 80, 255]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_splits_sheet_in_reading_order() {
+        let mut img = RgbaImage::new(100, 60);
+        fill(&mut img, 60, 4, 20, 12); // top right
+        fill(&mut img, 5, 8, 30, 3); // top left: an L with a lower top edge
+        fill(&mut img, 5, 8, 3, 10);
+        fill(&mut img, 20, 14, 4, 3); // detached detail inside the L's box
+        fill(&mut img, 10, 40, 15, 15); // second row
+        fill(&mut img, 30, 20, 1, 1); // stray pixel
+
+        let sprites = split_sprites(&img, 10);
+        let bounds: Vec<_> = sprites.iter().map(|s| s.bounds).collect();
+        assert_eq!(
+            bounds,
+            vec![(5, 8, 30, 10), (60, 4, 20, 12), (10, 40, 15, 15)]
+        );
+
+        let first = &sprites[0].image;
+        assert_eq!(
+            first.dimensions(),
+            (30 + 2 * SPRITE_PADDING, 10 + 2 * SPRITE_PADDING)
+        );
+        assert_eq!(first.get_pixel(0, 0)[3], 0);
+        assert_eq!(first.get_pixel(SPRITE_PADDING, SPRITE_PADDING)[3], 255);
+        assert_eq!(
+            first.get_pixel(20 - 5 + SPRITE_PADDING, 14 - 8 + SPRITE_PADDING)[3],
+            255
+        );
+    }
+
+    #[test]
+    fn test_grid_layout() {
+        let by_count = GridLayout {
+            grid: Some((4, 2)),
+            margin: 2,
+            spacing: 1,
+            ..GridLayout::default()
+        };
+        assert_eq!(by_count.resolve(71, 37).unwrap(), (4, 2, 16, 16));
+
+        let by_size = GridLayout {
+            grid: None,
+            cell: Some((16, 16)),
+            ..by_count
+        };
+        assert_eq!(by_size.resolve(75, 37).unwrap(), (4, 2, 16, 16));
+
+        let too_many = GridLayout {
+            grid: Some((8, 2)),
+            cell: Some((16, 16)),
+            ..GridLayout::default()
+        };
+        assert!(matches!(
+            too_many.resolve(64, 32),
+            Err(TrueIsoError::InvalidConfig(_))
+        ));
+        let empty = GridLayout {
+            grid: Some((0, 2)),
+            ..GridLayout::default()
+        };
+        assert!(empty.resolve(64, 32).is_err());
+    }
+
+    #[test]
+    fn test_slice_and_pack_grid() {
+        // 3x2 grid of 20 px cells with a 1 px gap; the middle cell of each row is empty
+        let mut img = RgbaImage::new(62, 41);
+        fill(&mut img, 2, 5, 10, 10);
+        fill(&mut img, 45, 21, 12, 15);
+        fill(&mut img, 0, 30, 4, 4);
+        let layout = GridLayout {
+            grid: Some((3, 2)),
+            spacing: 1,
+            ..GridLayout::default()
+        };
+        let cells = slice_grid(&img, &layout, 10).unwrap();
+        let bounds: Vec<_> = cells.iter().map(|c| c.as_ref().map(|s| s.bounds)).collect();
+        assert_eq!(
+            bounds,
+            vec![
+                Some((0, 0, 20, 20)),
+                None,
+                None,
+                Some((0, 21, 20, 20)),
+                None,
+                Some((42, 21, 20, 20)),
+            ]
+        );
+
+        let images = vec![
+            Some(RgbaImage::from_pixel(10, 6, Rgba([1, 2, 3, 255]))),
+            None,
+            Some(RgbaImage::from_pixel(6, 12, Rgba([1, 2, 3, 255]))),
+        ];
+        let sheet = pack_grid(&images, 2);
+        assert_eq!(sheet.dimensions(), (20, 24));
+        // Bottom-aligned and centered in the first cell, the third starts a second row
+        assert_eq!(sheet.get_pixel(0, 11)[3], 255);
+        assert_eq!(sheet.get_pixel(0, 5)[3], 0);
+        assert_eq!(sheet.get_pixel(2, 12)[3], 255);
+        assert_eq!(sheet.get_pixel(1, 12)[3], 0);
+    }
+
+    #[test]
+    fn test_clears_neighbours_inside_bounding_box() {
+        // An L-shaped sprite whose box would contain a separate sprite's corner
+        let mut img = RgbaImage::new(60, 60);
+        fill(&mut img, 0, 0, 40, 5);
+        fill(&mut img, 0, 0, 5, 40);
+        fill(&mut img, 30, 30, 20, 20);
+
+        let sprites = split_sprites(&img, 10);
+        assert_eq!(sprites.len(), 2);
+        let corner = sprites[0]
+            .image
+            .get_pixel(30 + SPRITE_PADDING, 30 + SPRITE_PADDING);
+        assert_eq!(corner[3], 0);
+        assert_eq!(sprites[1].bounds, (30, 30, 20, 20));
+    }
+}
@@ -1,28 +1,254 @@
+use clap::ValueEnum;
 use image::{Rgba, RgbaImage};
-use nalgebra::Matrix3;
+use nalgebra::{Matrix3, Vector3};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fmt;
+use tracing::debug;
 
 use crate::detection::find_sprite_bounds;
+use crate::error::{Result, TrueIsoError};
 use crate::geometry::{compute_output_bounds, transform_point};
 
+/// A resampling filter, used both by the warp and by the resize
+///
+/// Filters are separable kernels: implement `support` and `weight`, and `sample`
+/// convolves the pixels around a position with them. Filters that are not
+/// kernels (or have a faster special case) override `sample` instead.
+pub trait Interpolator: Sync {
+    /// Kernel radius in source pixels; the weight is zero beyond it
+    fn support(&self) -> f64;
+
+    /// Kernel weight at a distance of `t` source pixels
+    fn weight(&self, t: f64) -> f64;
+
+    /// Sample premultiplied pixel data at a fractional position
+    ///
+    /// Pixel centers lie at whole coordinates; positions beyond the border repeat the edge.
+    fn sample(
+        &self,
+        premultiplied: &[[f64; 4]],
+        width: u32,
+        height: u32,
+        x: f64,
+        y: f64,
+    ) -> [f64; 4] {
+        convolve(self, premultiplied, width, height, x, y)
+    }
+}
+
+/// Separable convolution with a filter's kernel around a fractional position
+fn convolve<I: Interpolator + ?Sized>(
+    filter: &I,
+    premultiplied: &[[f64; 4]],
+    width: u32,
+    height: u32,
+    x: f64,
+    y: f64,
+) -> [f64; 4] {
+    let taps = |center: f64, len: u32| {
+        let first = (center - filter.support()).floor() as i64 + 1;
+        let last = (center + filter.support()).floor() as i64;
+        let mut taps: Vec<(usize, f64)> = (first..=last)
+            .map(|i| {
+                let index = i.clamp(0, len as i64 - 1) as usize;
+                (index, filter.weight(center - i as f64))
+            })
+            .collect();
+        // Normalizing keeps flat areas flat for kernels that do not sum to one
+        let total: f64 = taps.iter().map(|&(_, w)| w).sum();
+        if total.abs() > f64::EPSILON {
+            taps.iter_mut().for_each(|(_, w)| *w /= total);
+        }
+        taps
+    };
+    let (columns, rows) = (taps(x, width), taps(y, height));
+
+    let mut result = [0.0; 4];
+    for &(py, wy) in &rows {
+        for &(px, wx) in &columns {
+            let pixel = premultiplied[py * width as usize + px];
+            for c in 0..4 {
+                result[c] += pixel[c] * wx * wy;
+            }
+        }
+    }
+    result
+}
+
+impl<T: Interpolator + ?Sized> Interpolator for &T {
+    fn support(&self) -> f64 {
+        (**self).support()
+    }
+
+    fn weight(&self, t: f64) -> f64 {
+        (**self).weight(t)
+    }
+
+    fn sample(
+        &self,
+        premultiplied: &[[f64; 4]],
+        width: u32,
+        height: u32,
+        x: f64,
+        y: f64,
+    ) -> [f64; 4] {
+        (**self).sample(premultiplied, width, height, x, y)
+    }
+}
+
+/// Built-in resampling filters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interpolation {
+    /// Nearest neighbour (blocky, keeps the exact source colors; for pixel art)
+    Nearest,
+    /// Bilinear (softer, no overshoot)
+    Bilinear,
+    /// Catmull-Rom bicubic (sharp, may overshoot into halos around high-contrast edges)
+    #[default]
+    Bicubic,
+    /// Mitchell-Netravali cubic (B = C = 1/3; a little softer than bicubic, barely overshoots)
+    Mitchell,
+    /// Lanczos with three lobes (sharpest, rings the most)
+    Lanczos3,
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interpolation::Nearest => write!(f, "nearest"),
+            Interpolation::Bilinear => write!(f, "bilinear"),
+            Interpolation::Bicubic => write!(f, "bicubic"),
+            Interpolation::Mitchell => write!(f, "mitchell"),
+            Interpolation::Lanczos3 => write!(f, "lanczos3"),
+        }
+    }
+}
+
+impl Interpolator for Interpolation {
+    fn support(&self) -> f64 {
+        match self {
+            Interpolation::Nearest => 0.5,
+            Interpolation::Bilinear => 1.0,
+            Interpolation::Bicubic | Interpolation::Mitchell => 2.0,
+            Interpolation::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(&self, t: f64) -> f64 {
+        let t = t.abs();
+        match self {
+            Interpolation::Nearest => {
+                if t <= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Interpolation::Bilinear => (1.0 - t).max(0.0),
+            Interpolation::Bicubic => cubic_kernel(t, 0.0, 0.5),
+            Interpolation::Mitchell => cubic_kernel(t, 1.0 / 3.0, 1.0 / 3.0),
+            Interpolation::Lanczos3 => {
+                if t < 3.0 {
+                    sinc(t) * sinc(t / 3.0)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    fn sample(
+        &self,
+        premultiplied: &[[f64; 4]],
+        width: u32,
+        height: u32,
+        x: f64,
+        y: f64,
+    ) -> [f64; 4] {
+        match self {
+            Interpolation::Nearest => nearest_interpolate(premultiplied, width, height, x, y),
+            Interpolation::Bilinear => bilinear_interpolate(premultiplied, width, height, x, y),
+            Interpolation::Bicubic => bicubic_interpolate(premultiplied, width, height, x, y),
+            Interpolation::Mitchell | Interpolation::Lanczos3 => {
+                convolve(self, premultiplied, width, height, x, y)
+            }
+        }
+    }
+}
+
+/// Mitchell-Netravali family of cubic kernels (`b = 0, c = 0.5` is Catmull-Rom)
+fn cubic_kernel(t: f64, b: f64, c: f64) -> f64 {
+    let (t2, t3) = (t * t, t * t * t);
+    let weight = if t < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * t3 + (-18.0 + 12.0 * b + 6.0 * c) * t2 + (6.0 - 2.0 * b)
+    } else if t < 2.0 {
+        (-b - 6.0 * c) * t3
+            + (6.0 * b + 30.0 * c) * t2
+            + (-12.0 * b - 48.0 * c) * t
+            + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+    weight / 6.0
+}
+
+/// Normalized sinc: `sin(πt) / πt`
+fn sinc(t: f64) -> f64 {
+    if t.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * t).sin() / (PI * t)
+    }
+}
+
 /// Premultiply alpha: RGB values are multiplied by alpha
 fn premultiply_alpha(img: &RgbaImage) -> Vec<[f64; 4]> {
-    let (width, height) = img.dimensions();
-    let mut result = Vec::with_capacity((width * height) as usize);
+    let row_len = img.width() as usize * 4;
+    if row_len == 0 {
+        return Vec::new();
+    }
 
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = img.get_pixel(x, y);
-            let alpha = pixel[3] as f64 / 255.0;
-            result.push([
-                pixel[0] as f64 * alpha,
-                pixel[1] as f64 * alpha,
-                pixel[2] as f64 * alpha,
-                pixel[3] as f64,
-            ]);
-        }
+    // Rows are processed in parallel; collecting keeps them in order
+    img.as_raw()
+        .par_chunks(row_len)
+        .flat_map_iter(|row| {
+            row.chunks_exact(4).map(|pixel| {
+                let alpha = pixel[3] as f64 / 255.0;
+                [
+                    pixel[0] as f64 * alpha,
+                    pixel[1] as f64 * alpha,
+                    pixel[2] as f64 * alpha,
+                    pixel[3] as f64,
+                ]
+            })
+        })
+        .collect()
+}
+
+/// Fill an output image row by row in parallel
+///
+/// `pixel_at(x, y)` is evaluated independently for every pixel, so the result is
+/// identical to a serial loop regardless of the number of threads.
+fn fill_rows_parallel<F>(output: &mut RgbaImage, pixel_at: F)
+where
+    F: Fn(u32, u32) -> Rgba<u8> + Sync,
+{
+    let row_len = output.width() as usize * 4;
+    if row_len == 0 {
+        return;
     }
 
-    result
+    output
+        .par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                pixel.copy_from_slice(&pixel_at(x as u32, y as u32).0);
+            }
+        });
 }
 
 /// Unpremultiply alpha: divide RGB by alpha
@@ -72,13 +298,13 @@ fn bicubic_interpolate(
 
     let mut result = [0.0; 4];
 
-    for j in 0..4 {
-        for i in 0..4 {
+    for (j, wyj) in wy.iter().enumerate() {
+        for (i, wxi) in wx.iter().enumerate() {
             let px = (x_floor + i as i32 - 1).clamp(0, width as i32 - 1) as u32;
             let py = (y_floor + j as i32 - 1).clamp(0, height as i32 - 1) as u32;
             let idx = (py * width + px) as usize;
 
-            let weight = wx[i] * wy[j];
+            let weight = wxi * wyj;
             for c in 0..4 {
                 result[c] += premultiplied[idx][c] * weight;
             }
@@ -88,8 +314,20 @@ fn bicubic_interpolate(
     result
 }
 
-/// Bilinear interpolation (faster, available for edge cleaning)
-#[allow(dead_code)]
+/// Nearest-neighbour sampling (the pixel whose center is closest)
+fn nearest_interpolate(
+    premultiplied: &[[f64; 4]],
+    width: u32,
+    height: u32,
+    x: f64,
+    y: f64,
+) -> [f64; 4] {
+    let px = (x + 0.5).floor().clamp(0.0, width as f64 - 1.0) as u32;
+    let py = (y + 0.5).floor().clamp(0.0, height as f64 - 1.0) as u32;
+    premultiplied[(py * width + px) as usize]
+}
+
+/// Bilinear interpolation (faster, no overshoot)
 fn bilinear_interpolate(
     premultiplied: &[[f64; 4]],
     width: u32,
@@ -127,13 +365,46 @@ fn bilinear_interpolate(
 }
 
 /// Apply an affine transformation to an image using inverse mapping
+///
+/// Fails with [`TrueIsoError::DegenerateTransform`] if the matrix is not invertible.
 pub fn apply_affine_transform(
     img: &RgbaImage,
     forward_matrix: &Matrix3<f64>,
-    verbose: bool,
-) -> RgbaImage {
+    interpolator: impl Interpolator,
+) -> Result<RgbaImage> {
+    apply_projective_transform(img, forward_matrix, interpolator)
+}
+
+/// Apply a projective transformation (homography) to an image using inverse mapping
+///
+/// Affine matrices are the special case with a `[0, 0, 1]` bottom row. Fails with
+/// [`TrueIsoError::DegenerateTransform`] if the matrix is not invertible or maps part
+/// of the image beyond the horizon.
+pub fn apply_projective_transform(
+    img: &RgbaImage,
+    forward_matrix: &Matrix3<f64>,
+    interpolator: impl Interpolator,
+) -> Result<RgbaImage> {
     let (src_width, src_height) = img.dimensions();
 
+    // Compute inverse matrix for backward mapping
+    let inverse_matrix = forward_matrix
+        .try_inverse()
+        .ok_or(TrueIsoError::DegenerateTransform(
+            "transform matrix is not invertible",
+        ))?;
+
+    // The image stays bounded only if no corner crosses the line at infinity
+    let (w, h) = (src_width as f64, src_height as f64);
+    let crosses_horizon = [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)]
+        .iter()
+        .any(|&(x, y)| (forward_matrix * Vector3::new(x, y, 1.0)).z <= 0.0);
+    if crosses_horizon {
+        return Err(TrueIsoError::DegenerateTransform(
+            "perspective transform maps the image beyond the horizon",
+        ));
+    }
+
     // Compute output dimensions
     let (new_width, new_height, offset_x, offset_y) =
         compute_output_bounds(forward_matrix, src_width, src_height);
@@ -142,21 +413,15 @@ pub fn apply_affine_transform(
     let new_width = new_width.max(1).min(src_width * 3);
     let new_height = new_height.max(1).min(src_height * 3);
 
-    if verbose {
-        eprintln!(
-            "Transform: {}x{} -> {}x{} (offset: {:.1}, {:.1})",
-            src_width, src_height, new_width, new_height, offset_x, offset_y
-        );
-    }
-
-    // Compute inverse matrix for backward mapping
-    let inverse_matrix = match forward_matrix.try_inverse() {
-        Some(inv) => inv,
-        None => {
-            eprintln!("Warning: Could not invert transform matrix, returning original image");
-            return img.clone();
-        }
-    };
+    debug!(
+        src_width,
+        src_height,
+        width = new_width,
+        height = new_height,
+        offset_x,
+        offset_y,
+        "applying projective transform"
+    );
 
     // Pre-multiply alpha for correct interpolation
     let premultiplied = premultiply_alpha(img);
@@ -164,32 +429,26 @@ pub fn apply_affine_transform(
     // Create output image
     let mut output = RgbaImage::new(new_width, new_height);
 
-    // Apply inverse mapping with bicubic interpolation
-    for out_y in 0..new_height {
-        for out_x in 0..new_width {
-            // Map output pixel to source coordinates
-            let dst_x = out_x as f64 + offset_x;
-            let dst_y = out_y as f64 + offset_y;
-            let (src_x, src_y) = transform_point(&inverse_matrix, dst_x, dst_y);
-
-            // Check if source is within bounds (with some margin for interpolation)
-            if src_x >= -1.0
-                && src_x <= src_width as f64
-                && src_y >= -1.0
-                && src_y <= src_height as f64
-            {
-                let interpolated =
-                    bicubic_interpolate(&premultiplied, src_width, src_height, src_x, src_y);
-                let pixel = unpremultiply_alpha(interpolated);
-                output.put_pixel(out_x, out_y, pixel);
-            } else {
-                output.put_pixel(out_x, out_y, Rgba([0, 0, 0, 0]));
-            }
+    // Apply inverse mapping (row-parallel)
+    fill_rows_parallel(&mut output, |out_x, out_y| {
+        // Map output pixel to source coordinates
+        let dst_x = out_x as f64 + offset_x;
+        let dst_y = out_y as f64 + offset_y;
+        let (src_x, src_y) = transform_point(&inverse_matrix, dst_x, dst_y);
+
+        // Check if source is within bounds (with some margin for interpolation)
+        if src_x >= -1.0 && src_x <= src_width as f64 && src_y >= -1.0 && src_y <= src_height as f64
+        {
+            let interpolated =
+                interpolator.sample(&premultiplied, src_width, src_height, src_x, src_y);
+            unpremultiply_alpha(interpolated)
+        } else {
+            Rgba([0, 0, 0, 0])
         }
-    }
+    });
 
     // Clean up edge artifacts
-    clean_edges(&mut output)
+    Ok(clean_edges(&mut output))
 }
 
 /// Remove edge artifacts by cleaning up semi-transparent edge pixels
@@ -227,8 +486,8 @@ fn clean_edges(img: &mut RgbaImage) -> RgbaImage {
 }
 
 /// Crop image to its non-transparent content (removes padding)
-pub fn crop_to_content(img: &RgbaImage) -> RgbaImage {
-    let bounds = match find_sprite_bounds(img, 10) {
+pub fn crop_to_content(img: &RgbaImage, alpha_threshold: u8) -> RgbaImage {
+    let bounds = match find_sprite_bounds(img, alpha_threshold) {
         Some(b) => b,
         None => return img.clone(), // Return original if fully transparent
     };
@@ -246,9 +505,21 @@ pub fn crop_to_content(img: &RgbaImage) -> RgbaImage {
     cropped
 }
 
+/// Copy an image onto a transparent `width`×`height` canvas with its top-left corner at `(x, y)`
+///
+/// Parts that fall outside the canvas are clipped.
+pub fn place_on_canvas(img: &RgbaImage, width: u32, height: u32, x: i64, y: i64) -> RgbaImage {
+    let mut canvas = RgbaImage::new(width, height);
+    image::imageops::replace(&mut canvas, img, x, y);
+    canvas
+}
+
 /// Resize image so that the longest side equals target_size
-/// Uses bicubic interpolation for quality
-pub fn resize_to_fit(img: &RgbaImage, target_size: u32) -> RgbaImage {
+pub fn resize_to_fit(
+    img: &RgbaImage,
+    target_size: u32,
+    interpolator: impl Interpolator,
+) -> RgbaImage {
     let (width, height) = img.dimensions();
 
     if width == 0 || height == 0 {
@@ -256,6 +527,17 @@ pub fn resize_to_fit(img: &RgbaImage, target_size: u32) -> RgbaImage {
     }
 
     let scale = target_size as f64 / width.max(height) as f64;
+    resize_by(img, scale, interpolator)
+}
+
+/// Scale both sides of an image by the same factor
+pub fn resize_by(img: &RgbaImage, scale: f64, interpolator: impl Interpolator) -> RgbaImage {
+    let (width, height) = img.dimensions();
+
+    if width == 0 || height == 0 {
+        return img.clone();
+    }
+
     let new_width = ((width as f64 * scale).round() as u32).max(1);
     let new_height = ((height as f64 * scale).round() as u32).max(1);
 
@@ -264,17 +546,14 @@ pub fn resize_to_fit(img: &RgbaImage, target_size: u32) -> RgbaImage {
 
     let mut output = RgbaImage::new(new_width, new_height);
 
-    for out_y in 0..new_height {
-        for out_x in 0..new_width {
-            // Map output coordinates to source coordinates
-            let src_x = (out_x as f64 + 0.5) / scale - 0.5;
-            let src_y = (out_y as f64 + 0.5) / scale - 0.5;
+    fill_rows_parallel(&mut output, |out_x, out_y| {
+        // Map output coordinates to source coordinates
+        let src_x = (out_x as f64 + 0.5) / scale - 0.5;
+        let src_y = (out_y as f64 + 0.5) / scale - 0.5;
 
-            let interpolated = bicubic_interpolate(&premultiplied, width, height, src_x, src_y);
-            let pixel = unpremultiply_alpha(interpolated);
-            output.put_pixel(out_x, out_y, pixel);
-        }
-    }
+        let interpolated = interpolator.sample(&premultiplied, width, height, src_x, src_y);
+        unpremultiply_alpha(interpolated)
+    });
 
     output
 }
@@ -297,11 +576,97 @@ mod tests {
         assert_eq!(unpremul[3], pixel[3]);
     }
 
+    #[test]
+    fn test_parallel_rows_match_serial() {
+        let img = RgbaImage::from_fn(37, 23, |x, y| {
+            Rgba([
+                (x * 7) as u8,
+                (y * 11) as u8,
+                ((x + y) * 3) as u8,
+                ((x * y) % 256) as u8,
+            ])
+        });
+        let premultiplied = premultiply_alpha(&img);
+
+        let mut serial = Vec::with_capacity(premultiplied.len());
+        for y in 0..img.height() {
+            for x in 0..img.width() {
+                let pixel = img.get_pixel(x, y);
+                let alpha = pixel[3] as f64 / 255.0;
+                serial.push([
+                    pixel[0] as f64 * alpha,
+                    pixel[1] as f64 * alpha,
+                    pixel[2] as f64 * alpha,
+                    pixel[3] as f64,
+                ]);
+            }
+        }
+        assert_eq!(premultiplied, serial);
+
+        let resized = resize_to_fit(&img, 64, Interpolation::Bicubic);
+        for (x, y, pixel) in resized.enumerate_pixels() {
+            let scale = 64.0 / 37.0;
+            let src_x = (x as f64 + 0.5) / scale - 0.5;
+            let src_y = (y as f64 + 0.5) / scale - 0.5;
+            let expected = unpremultiply_alpha(bicubic_interpolate(&serial, 37, 23, src_x, src_y));
+            assert_eq!(*pixel, expected);
+        }
+    }
+
+    #[test]
+    fn test_filters_match_their_kernels() {
+        let img = RgbaImage::from_fn(9, 7, |x, y| {
+            Rgba([(x * 29) as u8, (y * 31) as u8, ((x * y) * 5) as u8, 255])
+        });
+        let premultiplied = premultiply_alpha(&img);
+        let flat = premultiply_alpha(&RgbaImage::from_pixel(9, 7, Rgba([90, 140, 60, 200])));
+
+        let filters = [
+            Interpolation::Nearest,
+            Interpolation::Bilinear,
+            Interpolation::Bicubic,
+            Interpolation::Mitchell,
+            Interpolation::Lanczos3,
+        ];
+        for filter in filters {
+            for &(x, y) in &[(0.0, 0.0), (3.3, 2.7), (4.5, 5.25), (8.9, -0.4)] {
+                // The fast paths are the kernel's convolution
+                let fast = filter.sample(&premultiplied, 9, 7, x, y);
+                let kernel = convolve(&filter, &premultiplied, 9, 7, x, y);
+                for c in 0..4 {
+                    assert!(
+                        (fast[c] - kernel[c]).abs() < 1e-6,
+                        "{} at {:?}",
+                        filter,
+                        (x, y)
+                    );
+                }
+
+                let sample = filter.sample(&flat, 9, 7, x, y);
+                assert!(
+                    (sample[3] - 200.0).abs() < 1e-6,
+                    "{} at {:?}",
+                    filter,
+                    (x, y)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_nearest_keeps_source_colors() {
+        let colors = [Rgba([250, 20, 20, 255]), Rgba([20, 20, 250, 255])];
+        let img = RgbaImage::from_fn(8, 8, |x, y| colors[((x + y) % 2) as usize]);
+        let resized = resize_by(&img, 2.5, Interpolation::Nearest);
+        assert_eq!(resized.dimensions(), (20, 20));
+        assert!(resized.pixels().all(|pixel| colors.contains(pixel)));
+    }
+
     #[test]
     fn test_identity_transform() {
         let img = RgbaImage::from_pixel(10, 10, Rgba([255, 0, 0, 255]));
         let identity = Matrix3::identity();
-        let result = apply_affine_transform(&img, &identity, false);
+        let result = apply_affine_transform(&img, &identity, Interpolation::Bicubic).unwrap();
 
         // Should preserve dimensions and colors
         assert_eq!(result.dimensions(), (10, 10));
@@ -309,4 +674,24 @@ mod tests {
         assert_eq!(center[0], 255);
         assert_eq!(center[3], 255);
     }
+
+    #[test]
+    fn test_singular_transform_is_error() {
+        let img = RgbaImage::from_pixel(10, 10, Rgba([255, 0, 0, 255]));
+        let singular = Matrix3::new(1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0);
+        let result = apply_affine_transform(&img, &singular, Interpolation::Bicubic);
+        assert!(matches!(result, Err(TrueIsoError::DegenerateTransform(_))));
+    }
+
+    #[test]
+    fn test_projective_transform_beyond_horizon_is_error() {
+        let img = RgbaImage::from_pixel(10, 10, Rgba([255, 0, 0, 255]));
+        // w = 1 - 0.2 * x turns negative at the right edge
+        let perspective = Matrix3::new(1.0, 0.0, 0.0, 0.0, 1.0, 0.0, -0.2, 0.0, 1.0);
+        let result = apply_projective_transform(&img, &perspective, Interpolation::Bilinear);
+        assert!(matches!(result, Err(TrueIsoError::DegenerateTransform(_))));
+
+        let mild = Matrix3::new(1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.01, 0.0, 1.0);
+        assert!(apply_projective_transform(&img, &mild, Interpolation::Bilinear).is_ok());
+    }
 }